@@ -1,6 +1,6 @@
 use std::{cell::RefCell, collections::HashMap, hash::Hash};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Error, Result};
 use chrono::Utc;
 use futures::Future;
 use log::warn;
@@ -13,6 +13,8 @@ pub struct CacheEntry<V: Send> {
 
 pub struct TimedCache<K, V: Send> {
     expiration_duration: chrono::Duration,
+    // Keys that expire sooner or later than the rest.
+    key_expiration_durations: HashMap<K, chrono::Duration>,
     // TODO: Investigate if we need the inner RefCell
     entries: RefCell<HashMap<K, CacheEntry<V>>>,
 }
@@ -30,27 +32,52 @@ impl<K: Clone + Eq + Hash + std::fmt::Debug + ToString, V: Clone + Send + std::f
         // Will have to remove expired entries on a schedule, and not just their values on request.
         TimedCache {
             expiration_duration: duration,
+            key_expiration_durations: HashMap::new(),
             entries: RefCell::new(HashMap::new()),
         }
     }
 
+    pub fn set_expiration_duration(&mut self, key: K, duration: chrono::Duration) {
+        self.key_expiration_durations.insert(key, duration);
+    }
+
+    /// Keep the values of another cache, e.g. one built from an older config, until they expire.
+    pub fn copy_entries_from(&self, other: &TimedCache<K, V>) {
+        let other_entries = other.entries.borrow();
+        let mut entries = self.entries.borrow_mut();
+        for (key, entry) in other_entries.iter() {
+            entries.insert(key.clone(), entry.clone());
+        }
+    }
+
+    #[cfg(test)]
     pub async fn get_or_compute<F, Fut>(&self, key: K, f: F) -> Result<V>
     where
         F: FnOnce() -> Fut,
         Fut: Future<Output = Result<V>>,
     {
-        let entries = self.entries.borrow();
-        let value = entries.get(&key).map(|v| v.clone());
-        drop(entries);
+        self.get_or_refresh(key, |_| async { f().await.map(Some) })
+            .await
+    }
+
+    /// Like get_or_compute, but f is given the expired value, and can return None
+    /// to keep using it for another expiration duration.
+    pub async fn get_or_refresh<F, Fut>(&self, key: K, f: F) -> Result<V>
+    where
+        F: FnOnce(Option<V>) -> Fut,
+        Fut: Future<Output = Result<Option<V>>>,
+    {
+        let value = self.entries.borrow().get(&key).cloned();
 
         match value {
             Some(entry) => {
                 if entry.expiration_date_time.timestamp() > chrono::offset::Utc::now().timestamp() {
                     Ok(entry.value.clone())
                 } else {
-                    let result = f().await;
+                    let result = f(Some(entry.value.clone())).await;
                     if let Ok(new_value) = result {
-                        let entry = self.new_cache_entry(new_value.clone());
+                        let new_value = new_value.unwrap_or(entry.value);
+                        let entry = self.new_cache_entry(&key, new_value.clone());
                         let mut entries = self.entries.borrow_mut();
                         entries.insert(key.clone(), entry);
                         Ok(new_value)
@@ -61,26 +88,31 @@ impl<K: Clone + Eq + Hash + std::fmt::Debug + ToString, V: Clone + Send + std::f
                 }
             }
             _ => {
-                let result = f().await;
+                let result = f(None).await;
 
-                match &result {
-                    Ok(value) => {
-                        let entry = self.new_cache_entry(value.clone());
+                match result {
+                    Ok(Some(value)) => {
+                        let entry = self.new_cache_entry(&key, value.clone());
                         let mut entries = self.entries.borrow_mut();
                         entries.insert(key.clone(), entry);
-                        result
+                        Ok(value)
                     }
-                    Err(_) => result.context("Failed to compute a successful response when there was nothing cached to use."),
+                    Ok(None) => Err(Error::msg("There was no previous value to keep using.")),
+                    Err(err) => Err(err).context("Failed to compute a successful response when there was nothing cached to use."),
                 }
             }
         }
     }
 
-    fn new_cache_entry(&self, value: V) -> CacheEntry<V> {
+    fn new_cache_entry(&self, key: &K, value: V) -> CacheEntry<V> {
+        let duration = self
+            .key_expiration_durations
+            .get(key)
+            .unwrap_or(&self.expiration_duration);
         CacheEntry {
-            value: value,
+            value,
             expiration_date_time: chrono::offset::Utc::now()
-                .checked_add_signed(self.expiration_duration)
+                .checked_add_signed(*duration)
                 .unwrap(),
         }
     }
@@ -150,4 +182,59 @@ mod test {
 
         assert_eq!("y", r2);
     }
+
+    #[actix_rt::test]
+    async fn keys_can_have_their_own_expiration_duration() {
+        let mut c = cache();
+        c.set_expiration_duration(url(), chrono::Duration::zero());
+
+        let _ = c
+            .get_or_compute(url(), || future::lazy(|_| Ok("x")))
+            .await
+            .unwrap();
+
+        let r2 = c
+            .get_or_compute(url(), || future::lazy(|_| Ok("y")))
+            .await
+            .unwrap();
+
+        assert_eq!("y", r2);
+    }
+
+    #[actix_rt::test]
+    async fn entries_can_be_copied_from_another_cache() {
+        let old = cache();
+        let _ = old
+            .get_or_compute(url(), || future::lazy(|_| Ok("x")))
+            .await
+            .unwrap();
+
+        let c = cache();
+        c.copy_entries_from(&old);
+        let r = c
+            .get_or_compute(url(), || future::lazy(|_| Ok("y")))
+            .await
+            .unwrap();
+
+        assert_eq!("x", r);
+    }
+
+    #[actix_rt::test]
+    async fn expired_entries_can_be_kept_when_they_are_still_fresh() {
+        let c = cache2(chrono::Duration::zero());
+        let _ = c
+            .get_or_compute(url(), || future::lazy(|_| Ok("x")))
+            .await
+            .unwrap();
+
+        let r2 = c
+            .get_or_refresh(url(), |previous| {
+                assert_eq!(Some("x"), previous);
+                future::lazy(|_| Ok(None))
+            })
+            .await
+            .unwrap();
+
+        assert_eq!("x", r2);
+    }
 }
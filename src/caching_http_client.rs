@@ -6,10 +6,11 @@ use bytes::Bytes;
 use reqwest::Url;
 
 use crate::cache::TimedCache;
-use crate::http_client::HttpClient;
+use crate::http_client::{HttpClient, HttpResource};
+use crate::redact::Redacted;
 
 pub struct CachingHttpClient {
-    cache: TimedCache<Url, Bytes>,
+    cache: TimedCache<Url, HttpResource>,
     delegate: Rc<dyn HttpClient>,
 }
 
@@ -24,16 +25,109 @@ impl CachingHttpClient {
             cache: TimedCache::from_expiration_duration_and_keys(duration, feed_urls),
         }
     }
+
+    /// Cache the given urls for a different duration than the default.
+    pub fn with_expiration_durations<I: Iterator<Item = (Url, chrono::Duration)>>(
+        mut self,
+        durations: I,
+    ) -> CachingHttpClient {
+        for (url, duration) in durations {
+            self.cache.set_expiration_duration(url, duration);
+        }
+        self
+    }
+
+    /// Start out with the responses another client has cached.
+    pub fn with_cache_of(self, other: &CachingHttpClient) -> CachingHttpClient {
+        self.cache.copy_entries_from(&other.cache);
+        self
+    }
 }
 
 #[async_trait(?Send)]
 impl HttpClient for CachingHttpClient {
     async fn get_bytes(&self, url: &Url) -> Result<Bytes> {
-        let result = self
-            .cache
-            .get_or_compute(url.clone(), || self.delegate.get_bytes(url))
+        Ok(self.get_resource(url).await?.bytes)
+    }
+
+    async fn get_resource(&self, url: &Url) -> Result<HttpResource> {
+        self.cache
+            .get_or_refresh(url.clone(), |previous| async move {
+                // Ask whether the cached response changed, instead of downloading it again.
+                match previous {
+                    Some(previous) => self.delegate.get_resource_if_modified(url, &previous).await,
+                    None => Ok(Some(self.delegate.get_resource(url).await?)),
+                }
+            })
             .await
-            .with_context(|| format!("Failed get cache http request {}, or compute it", url));
-        result
+            .with_context(|| {
+                format!(
+                    "Failed get cache http request {}, or compute it",
+                    Redacted(url)
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    // Serves a single version of a resource, and records what it's asked to revalidate.
+    struct NotModifiedHttpClient {
+        resource: HttpResource,
+        revalidated: RefCell<Vec<HttpResource>>,
+    }
+
+    #[async_trait(?Send)]
+    impl HttpClient for NotModifiedHttpClient {
+        async fn get_bytes(&self, _url: &Url) -> Result<Bytes> {
+            Ok(self.resource.bytes.clone())
+        }
+
+        async fn get_resource(&self, _url: &Url) -> Result<HttpResource> {
+            Ok(self.resource.clone())
+        }
+
+        async fn get_resource_if_modified(
+            &self,
+            _url: &Url,
+            previous: &HttpResource,
+        ) -> Result<Option<HttpResource>> {
+            self.revalidated.borrow_mut().push(previous.clone());
+            Ok(None)
+        }
+    }
+
+    #[actix_rt::test]
+    async fn expired_responses_are_revalidated_and_kept_when_not_modified() {
+        let url = Url::parse("https://example.com/rss").unwrap();
+        let resource = HttpResource {
+            bytes: Bytes::from_static(b"<rss />"),
+            content_type: Some("application/rss+xml".into()),
+            etag: Some(r#""v1""#.into()),
+            last_modified: Some("Sun, 08 May 2022 07:04:26 GMT".into()),
+        };
+        let delegate = Rc::new(NotModifiedHttpClient {
+            resource: resource.clone(),
+            revalidated: RefCell::new(vec![]),
+        });
+        // Responses expire right away, so every request after the first one is a revalidation.
+        let client = CachingHttpClient::new(
+            delegate.clone(),
+            chrono::Duration::zero(),
+            std::iter::empty(),
+        );
+
+        assert_eq!(client.get_resource(&url).await.unwrap(), resource);
+        assert!(delegate.revalidated.borrow().is_empty());
+
+        assert_eq!(client.get_resource(&url).await.unwrap(), resource);
+        assert_eq!(client.get_resource(&url).await.unwrap(), resource);
+        assert_eq!(
+            *delegate.revalidated.borrow(),
+            vec![resource.clone(), resource]
+        );
     }
 }
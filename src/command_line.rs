@@ -0,0 +1,96 @@
+use anyhow::{Error, Result};
+
+pub const USAGE: &str = "Usage: feedragon [OPTIONS]
+
+Options:
+    --config <PATH>              Config file to read [default: feedragon.toml]
+    --bind-address <ADDRESS>     Address to listen on [default: 0.0.0.0]
+    --port <PORT>                Port to listen on [default: 8080]
+    --workers <COUNT>            Number of worker threads [default: 1]
+    --public-base-url <URL>      Url feedragon is reachable at, used for self links
+    --libreddit-url <URL>        Libreddit instance to rewrite reddit links to
+    --cache-ttl-minutes <COUNT>  How long feeds are cached [default: 60]
+    --help                       Print this message
+
+Every option can also be set with an environment variable, e.g. FEEDRAGON_PORT=8081.
+Options take precedence over environment variables, which take precedence over the config file.";
+
+#[derive(Debug, PartialEq, Default)]
+pub struct CommandLine {
+    pub config_path: Option<String>,
+    pub help: bool,
+    /// Server settings by their name in the config file, in the order they were given.
+    pub server_settings: Vec<(String, String)>,
+}
+
+impl CommandLine {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<CommandLine> {
+        let mut command_line = CommandLine::default();
+        while let Some(arg) = args.next() {
+            let option = arg
+                .strip_prefix("--")
+                .ok_or_else(|| Error::msg(format!("Unexpected argument {}", arg)))?;
+            if option == "help" {
+                command_line.help = true;
+                continue;
+            }
+            // Both --port 8081 and --port=8081 are accepted.
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| Error::msg(format!("Missing value for {}", arg)))?;
+                    (option.to_string(), value)
+                }
+            };
+            match name.as_str() {
+                "config" => command_line.config_path = Some(value),
+                _ => command_line
+                    .server_settings
+                    .push((name.replace('-', "_"), value)),
+            }
+        }
+        Ok(command_line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CommandLine> {
+        CommandLine::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_can_be_separate_or_joined_with_their_value() {
+        let command_line = parse(&[
+            "--config",
+            "staging.toml",
+            "--port=8081",
+            "--bind-address",
+            "127.0.0.1",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            command_line,
+            CommandLine {
+                config_path: Some("staging.toml".into()),
+                help: false,
+                server_settings: vec![
+                    ("port".into(), "8081".into()),
+                    ("bind_address".into(), "127.0.0.1".into())
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn missing_values_and_stray_arguments_are_errors() {
+        assert!(parse(&["--port"]).is_err());
+        assert!(parse(&["feedragon.toml"]).is_err());
+        assert!(parse(&["--help"]).unwrap().help);
+    }
+}
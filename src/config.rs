@@ -1,16 +1,705 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Error, Result};
+use chrono::Duration;
+use regex::Regex;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
 use serde_derive::Deserialize;
+use url::Url;
+
+use crate::feed::filter::FilterRule;
+use crate::feed::model::{DedupStrategy, MergeOptions, SortKey};
+use crate::feed::rewrite::RewriteRule;
+use crate::feed_transformer::Transformation;
+use crate::redact::redact_text;
 
 #[derive(Deserialize)]
 pub struct Config {
-    pub categories: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// Url rewrites applied to the entries of every source, in order.
+    #[serde(default)]
+    pub rewrites: Vec<RewriteRule>,
+    /// Keys that give access to every category.
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// Query parameters masked in logs and errors, besides common ones like token and key.
+    #[serde(default)]
+    pub secret_params: Vec<String>,
+    pub categories: HashMap<String, CategoryConfig>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    pub workers: usize,
+    /// The url feedragon is reachable at, which self links are made from.
+    /// When missing, it's taken from the Host and X-Forwarded-* headers of each request.
+    pub public_base_url: Option<String>,
+    /// The libreddit instance reddit links are rewritten to, and reddit images are proxied through.
+    pub libreddit_url: Option<String>,
+    /// How long downloaded feeds are cached, unless their source says otherwise.
+    pub cache_ttl_minutes: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind_address: "0.0.0.0".into(),
+            port: 8080,
+            workers: 1,
+            public_base_url: None,
+            libreddit_url: None,
+            cache_ttl_minutes: 60,
+        }
+    }
+}
+
+// Environment variables override the config file, e.g. FEEDRAGON_PORT=8081.
+const ENV_PREFIX: &str = "FEEDRAGON_";
+
+// The settings that can be overridden by name, from the command line or the environment.
+const SETTING_NAMES: [&str; 6] = [
+    "bind_address",
+    "port",
+    "workers",
+    "public_base_url",
+    "libreddit_url",
+    "cache_ttl_minutes",
+];
+
+fn is_service_link(value: &str) -> bool {
+    ["tcp://", "udp://", "sctp://"]
+        .iter()
+        .any(|scheme| value.starts_with(scheme))
+}
+
+/// Parse a url that paths are joined onto, which only keeps its own path when it ends with a slash.
+pub fn base_url(s: &str) -> Result<Url> {
+    let url = if s.ends_with('/') {
+        Url::parse(s)
+    } else {
+        Url::parse(&format!("{}/", s))
+    };
+    url.with_context(|| format!("Failed to parse base url {}", s))
+}
+
+impl ServerConfig {
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::minutes(self.cache_ttl_minutes.into())
+    }
+
+    pub fn parsed_public_base_url(&self) -> Result<Option<Url>> {
+        self.public_base_url
+            .as_deref()
+            .map(base_url)
+            .transpose()
+            .context("Invalid public_base_url")
+    }
+
+    pub fn parsed_libreddit_url(&self) -> Result<Option<Url>> {
+        self.libreddit_url
+            .as_deref()
+            .map(base_url)
+            .transpose()
+            .context("Invalid libreddit_url")
+    }
+
+    /// Override a setting by its name in the config file.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let parse_error = || format!("Invalid value '{}' for server setting {}", value, name);
+        match name {
+            "bind_address" => self.bind_address = value.into(),
+            "port" => self.port = value.parse().with_context(parse_error)?,
+            "workers" => {
+                let updated = ServerConfig {
+                    workers: value.parse().with_context(parse_error)?,
+                    ..self.clone()
+                };
+                updated.validate().with_context(parse_error)?;
+                *self = updated;
+            }
+            "public_base_url" => self.public_base_url = Some(value.into()),
+            "libreddit_url" => self.libreddit_url = Some(value.into()),
+            "cache_ttl_minutes" => {
+                self.cache_ttl_minutes = value.parse().with_context(parse_error)?
+            }
+            _ => return Err(Error::msg(format!("Unknown server setting {}", name))),
+        }
+        Ok(())
+    }
+
+    /// Settings that can't be checked by their type alone.
+    pub fn validate(&self) -> Result<()> {
+        if self.workers == 0 {
+            return Err(Error::msg("There has to be at least one worker"));
+        }
+        Ok(())
+    }
+
+    /// Override settings from FEEDRAGON_* environment variables named after them, ignoring everything else,
+    /// such as the FEEDRAGON_SERVICE_HOST that Kubernetes sets for a service named feedragon.
+    pub fn set_from_env<I: Iterator<Item = (String, String)>>(&mut self, vars: I) -> Result<()> {
+        for (key, value) in vars {
+            let name = match key.strip_prefix(ENV_PREFIX) {
+                Some(name) => name.to_lowercase(),
+                None => continue,
+            };
+            // Kubernetes also sets FEEDRAGON_PORT to the address of the service, like tcp://10.0.0.1:8080.
+            if is_service_link(&value) {
+                log::warn!(
+                    "Ignoring {}, which looks like a Kubernetes service link.",
+                    key
+                );
+                continue;
+            }
+            if SETTING_NAMES.contains(&name.as_str()) {
+                self.set(&name, &value)
+                    .with_context(|| format!("Invalid environment variable {}", key))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(from = "CategoryConfigFormat")]
+pub struct CategoryConfig {
+    pub sources: Vec<SourceConfig>,
+    pub sort_by: SortKey,
+    pub skip_invalid_entries: bool,
+    pub dedup: DedupStrategy,
+    pub max_entries: Option<usize>,
+    pub max_age_days: Option<u32>,
+    pub max_entries_per_source: Option<usize>,
+    pub filters: Vec<FilterRule>,
+    /// Keys that give access to this category, in addition to the global ones.
+    pub keys: Vec<String>,
+}
+
+// A category is either a plain list of urls, or a table with additional settings.
+enum CategoryConfigFormat {
+    Sources(Vec<SourceConfig>),
+    Table(CategoryTable),
+}
+
+// Picked by the type of the value instead of with an untagged enum, which would replace the errors
+// of the table, like a misspelled setting, with one that doesn't say what's wrong.
+impl<'de> Deserialize<'de> for CategoryConfigFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FormatVisitor;
+
+        impl<'de> Visitor<'de> for FormatVisitor {
+            type Value = CategoryConfigFormat;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of sources, or a table with sources and settings")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Deserialize::deserialize(SeqAccessDeserializer::new(seq))
+                    .map(CategoryConfigFormat::Sources)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                Deserialize::deserialize(MapAccessDeserializer::new(map))
+                    .map(CategoryConfigFormat::Table)
+            }
+        }
+
+        deserializer.deserialize_any(FormatVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CategoryTable {
+    sources: Vec<SourceConfig>,
+    #[serde(default)]
+    sort_by: SortKey,
+    #[serde(default = "default_skip_invalid_entries")]
+    skip_invalid_entries: bool,
+    #[serde(default)]
+    dedup: DedupStrategy,
+    max_entries: Option<usize>,
+    max_age_days: Option<u32>,
+    max_entries_per_source: Option<usize>,
+    #[serde(default)]
+    filters: Vec<FilterRule>,
+    #[serde(default)]
+    keys: Vec<String>,
+}
+
+impl From<CategoryConfigFormat> for CategoryConfig {
+    fn from(format: CategoryConfigFormat) -> CategoryConfig {
+        match format {
+            CategoryConfigFormat::Sources(sources) => CategoryConfig {
+                sources,
+                ..CategoryConfig::from(vec![])
+            },
+            CategoryConfigFormat::Table(table) => CategoryConfig {
+                sources: table.sources,
+                sort_by: table.sort_by,
+                skip_invalid_entries: table.skip_invalid_entries,
+                dedup: table.dedup,
+                max_entries: table.max_entries,
+                max_age_days: table.max_age_days,
+                max_entries_per_source: table.max_entries_per_source,
+                filters: table.filters,
+                keys: table.keys,
+            },
+        }
+    }
+}
+
+impl CategoryConfig {
+    pub fn merge_options(&self) -> MergeOptions {
+        MergeOptions {
+            sort_by: self.sort_by,
+            dedup: self.dedup,
+            max_entries: self.max_entries,
+            max_age: self.max_age_days.map(|days| Duration::days(days.into())),
+            max_entries_per_source: self.max_entries_per_source,
+        }
+    }
+}
+
+impl From<Vec<String>> for CategoryConfig {
+    fn from(sources: Vec<String>) -> CategoryConfig {
+        CategoryConfig {
+            sources: sources.into_iter().map(SourceConfig::from).collect(),
+            sort_by: SortKey::default(),
+            skip_invalid_entries: default_skip_invalid_entries(),
+            dedup: DedupStrategy::default(),
+            max_entries: None,
+            max_age_days: None,
+            max_entries_per_source: None,
+            filters: vec![],
+            keys: vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(from = "SourceConfigFormat")]
+pub struct SourceConfig {
+    pub url: String,
+    /// Replaces the title of the feed, which is also what its entries are attributed to.
+    pub name: Option<String>,
+    pub cache_ttl_minutes: Option<u32>,
+    /// Extra request headers, e.g. for feeds behind authentication.
+    pub headers: HashMap<String, String>,
+    /// Transformations applied to the feed in order, before it's merged into the category.
+    pub transformers: Vec<Transformation>,
+    pub enabled: bool,
+    pub filters: Vec<FilterRule>,
+}
+
+// A source is either a plain url, or a table with additional settings.
+enum SourceConfigFormat {
+    Url(String),
+    Table(SourceTable),
+}
+
+impl<'de> Deserialize<'de> for SourceConfigFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FormatVisitor;
+
+        impl<'de> Visitor<'de> for FormatVisitor {
+            type Value = SourceConfigFormat;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a url, or a table with a url and settings")
+            }
+
+            fn visit_str<E: de::Error>(self, url: &str) -> Result<Self::Value, E> {
+                Ok(SourceConfigFormat::Url(url.into()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                Deserialize::deserialize(MapAccessDeserializer::new(map))
+                    .map(SourceConfigFormat::Table)
+            }
+        }
+
+        deserializer.deserialize_any(FormatVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourceTable {
+    url: String,
+    name: Option<String>,
+    cache_ttl_minutes: Option<u32>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    transformers: Vec<Transformation>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    #[serde(default)]
+    filters: Vec<FilterRule>,
+}
+
+impl From<SourceConfigFormat> for SourceConfig {
+    fn from(format: SourceConfigFormat) -> SourceConfig {
+        match format {
+            SourceConfigFormat::Url(url) => SourceConfig::from(url),
+            SourceConfigFormat::Table(table) => SourceConfig {
+                url: table.url,
+                name: table.name,
+                cache_ttl_minutes: table.cache_ttl_minutes,
+                headers: table.headers,
+                transformers: table.transformers,
+                enabled: table.enabled,
+                filters: table.filters,
+            },
+        }
+    }
+}
+
+impl From<String> for SourceConfig {
+    fn from(url: String) -> SourceConfig {
+        SourceConfig {
+            url,
+            name: None,
+            cache_ttl_minutes: None,
+            headers: HashMap::new(),
+            transformers: vec![],
+            enabled: default_enabled(),
+            filters: vec![],
+        }
+    }
+}
+
+impl SourceConfig {
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache_ttl_minutes
+            .map(|minutes| Duration::minutes(minutes.into()))
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_skip_invalid_entries() -> bool {
+    true
+}
+
+/// Leave out the values toml errors quote, like the key in `invalid type: string "a-key"`,
+/// keeping the key path and position that say where the mistake is.
+fn without_quoted_values(message: &str) -> String {
+    static QUOTED: OnceLock<Regex> = OnceLock::new();
+    let quoted = QUOTED.get_or_init(|| Regex::new(r#"\s*"(?:[^"\\]|\\.)*""#).unwrap());
+    quoted.replace_all(message, "").into_owned()
 }
 
 impl Config {
     pub fn from_toml_str(s: &str) -> Result<Config> {
-        let result = toml::from_str::<Config>(&s);
-        result.with_context(|| format!("Failed to parse feedragon config {}", s))
+        // The config itself is left out of the error, since it contains keys and source urls with secrets.
+        let config = toml::from_str::<Config>(s)
+            .map_err(|err| Error::msg(redact_text(&without_quoted_values(&err.to_string()))))
+            .context("Failed to parse feedragon config")?;
+        config
+            .server
+            .validate()
+            .context("Invalid server settings in feedragon config")?;
+        Ok(config)
+    }
+
+    /// The configured rewrites, followed by one from reddit to the libreddit instance if there is one.
+    /// The instance is taken from the given server settings, which are the ones the server started with
+    /// after a reload, so links go to the same instance as the image redirects.
+    pub fn rewrite_rules(&self, server: &ServerConfig) -> Result<Vec<RewriteRule>> {
+        let libreddit_rule = server
+            .parsed_libreddit_url()?
+            .map(|url| RewriteRule::host("www.reddit.com", &url))
+            .transpose()?;
+        Ok(self
+            .rewrites
+            .iter()
+            .cloned()
+            .chain(libreddit_rule)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::feed::filter::{FilterAction, FilterField, Pattern};
+    use crate::feed::model::Entry;
+    use crate::feed::rewrite::rewrite;
+
+    #[test]
+    fn categories_can_be_plain_url_lists() {
+        let config = Config::from_toml_str(
+            r#"
+            [categories]
+            comedy = ["https://nitter.net/HardDriveMag/rss"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.categories["comedy"],
+            CategoryConfig {
+                sources: vec![SourceConfig::from(
+                    "https://nitter.net/HardDriveMag/rss".to_string()
+                )],
+                sort_by: SortKey::Published,
+                skip_invalid_entries: true,
+                dedup: DedupStrategy::Off,
+                max_entries: None,
+                max_age_days: None,
+                max_entries_per_source: None,
+                filters: vec![],
+                keys: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn categories_can_be_tables_with_settings() {
+        let config = Config::from_toml_str(
+            r#"
+            [categories.comedy]
+            sources = ["https://nitter.net/HardDriveMag/rss"]
+            sort_by = "updated"
+            skip_invalid_entries = false
+            dedup = "title"
+            max_entries = 50
+            max_age_days = 7
+            max_entries_per_source = 10
+            filters = [{ exclude = "giveaway" }]
+            keys = ["comedy-key"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.categories["comedy"],
+            CategoryConfig {
+                sources: vec![SourceConfig::from(
+                    "https://nitter.net/HardDriveMag/rss".to_string()
+                )],
+                sort_by: SortKey::Updated,
+                skip_invalid_entries: false,
+                dedup: DedupStrategy::Title,
+                max_entries: Some(50),
+                max_age_days: Some(7),
+                max_entries_per_source: Some(10),
+                filters: vec![FilterRule {
+                    action: FilterAction::Exclude,
+                    pattern: Pattern::Keyword("giveaway".into()),
+                    fields: vec![
+                        FilterField::Title,
+                        FilterField::Summary,
+                        FilterField::Link,
+                        FilterField::Author
+                    ],
+                }],
+                keys: vec!["comedy-key".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn sources_can_be_urls_or_tables() {
+        let config = Config::from_toml_str(
+            r#"
+            [categories.videos]
+            sources = [
+                "https://nitter.net/HardDriveMag/rss",
+                { url = "https://invidious.example.com/feed/private", name = "Subscriptions", cache_ttl_minutes = 10, headers = { Authorization = "Basic dXNlcjpwYXNz" }, transformers = ["extract_images"], filters = [{ exclude = "short" }] },
+                { url = "https://old.example.com/rss", enabled = false },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.categories["videos"].sources,
+            vec![
+                SourceConfig::from("https://nitter.net/HardDriveMag/rss".to_string()),
+                SourceConfig {
+                    url: "https://invidious.example.com/feed/private".into(),
+                    name: Some("Subscriptions".into()),
+                    cache_ttl_minutes: Some(10),
+                    headers: [("Authorization".into(), "Basic dXNlcjpwYXNz".into())].into(),
+                    transformers: vec![Transformation::ExtractImages],
+                    enabled: true,
+                    filters: vec![FilterRule {
+                        action: FilterAction::Exclude,
+                        pattern: Pattern::Keyword("short".into()),
+                        fields: vec![
+                            FilterField::Title,
+                            FilterField::Summary,
+                            FilterField::Link,
+                            FilterField::Author
+                        ],
+                    }],
+                },
+                SourceConfig {
+                    enabled: false,
+                    ..SourceConfig::from("https://old.example.com/rss".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn misspelled_settings_are_rejected() {
+        let error = Config::from_toml_str(
+            r#"
+            [categories.videos]
+            sources = [{ url = "https://old.example.com/rss", enabeld = false }]
+            "#,
+        )
+        .err()
+        .unwrap();
+
+        assert!(
+            format!("{:#}", error).contains("unknown field `enabeld`"),
+            "Unexpected error {:#}",
+            error
+        );
+        let error = Config::from_toml_str(
+            r#"
+            [categories.videos]
+            sources = ["https://nitter.net/HardDriveMag/rss"]
+            max_entrie = 50
+            "#,
+        )
+        .err()
+        .unwrap();
+
+        assert!(
+            format!("{:#}", error).contains("unknown field `max_entrie`"),
+            "Unexpected error {:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn server_settings_default_and_can_be_overridden() {
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            port = 9000
+            public_base_url = "https://feeds.example.com"
+
+            [categories]
+            "#,
+        )
+        .unwrap();
+        let mut server = config.server;
+
+        assert_eq!(
+            server,
+            ServerConfig {
+                port: 9000,
+                public_base_url: Some("https://feeds.example.com".into()),
+                ..ServerConfig::default()
+            }
+        );
+
+        server
+            .set_from_env(
+                [
+                    ("FEEDRAGON_WORKERS".to_string(), "4".to_string()),
+                    ("FEEDRAGON_CONFIG".to_string(), "staging.toml".to_string()),
+                    ("FEEDRAGON_SERVICE_HOST".to_string(), "10.0.0.1".to_string()),
+                    (
+                        "FEEDRAGON_PORT".to_string(),
+                        "tcp://10.0.0.1:8080".to_string(),
+                    ),
+                    ("HOME".to_string(), "/root".to_string()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        server.set("port", "9001").unwrap();
+
+        assert_eq!((server.workers, server.port), (4, 9001));
+        assert!(server.set("port", "http").is_err());
+        assert!(server.set("prot", "9001").is_err());
+        assert!(server.set("workers", "0").is_err());
+        assert!(Config::from_toml_str("[server]\nworkers = 0\n[categories]").is_err());
+    }
+
+    #[test]
+    fn base_urls_keep_their_path() {
+        let url = base_url("https://example.com/feedragon").unwrap();
+
+        assert_eq!(
+            url.join("feeds/comedy/atom.xml").unwrap().as_str(),
+            "https://example.com/feedragon/feeds/comedy/atom.xml"
+        );
+    }
+
+    #[test]
+    fn parse_errors_leave_out_the_config() {
+        let error = Config::from_toml_str(
+            r#"
+            keys = ["a-secret-key"]
+            [categories]
+            comedy = 1
+            "#,
+        )
+        .err()
+        .unwrap();
+
+        assert!(!format!("{:#}", error).contains("a-secret-key"));
+    }
+
+    #[test]
+    fn parse_errors_leave_out_values_of_the_wrong_type() {
+        let error = Config::from_toml_str(
+            r#"
+            keys = "a-secret-key"
+            [categories]
+            "#,
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to parse feedragon config: invalid type: string, expected a sequence for key `keys` at line 2 column 20"
+        );
+    }
+
+    #[test]
+    fn libreddit_url_is_rewritten_to_after_other_rewrites() {
+        let config = Config::from_toml_str(
+            r#"
+            rewrites = [{ host = "twitter.com", to = "https://nitter.example.com" }]
+
+            [server]
+            libreddit_url = "https://example.com/libreddit"
+
+            [categories]
+            "#,
+        )
+        .unwrap();
+        let rules = config.rewrite_rules(&config.server).unwrap();
+
+        assert_eq!(rules[0], config.rewrites[0]);
+        // The path of the instance is kept, like the image redirects do.
+        let mut entry = Entry::for_test("https://www.reddit.com/r/rust/comments/1");
+        rewrite(&mut entry, &rules);
+        assert_eq!(
+            entry.link,
+            "https://example.com/libreddit/r/rust/comments/1"
+        );
     }
 }
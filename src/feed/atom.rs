@@ -1,3 +1,6 @@
+// The yaserde derive macros generate impl blocks inside anonymous consts.
+#![allow(non_local_definitions)]
+
 use derive_alias::derive_alias;
 use yaserde_derive::YaDeserialize;
 use yaserde_derive::YaSerialize;
@@ -10,6 +13,7 @@ derive_item! {
     #[yaserde(
         prefix = "ns",
         namespace = "ns: http://www.w3.org/2005/Atom"
+        namespace = "media: http://search.yahoo.com/mrss/"
         root = "feed"
         rename = "feed"
     )]
@@ -20,6 +24,8 @@ derive_item! {
         pub title: String,
         #[yaserde(prefix="ns")]
         pub updated: String,
+        #[yaserde(prefix="ns")]
+        pub author: Option<AtomAuthor>,
         #[yaserde(rename="entry", prefix="ns")]
         pub entries: Vec<AtomEntry>,
     }
@@ -29,16 +35,54 @@ derive_item! {
     #[yaserde(
         prefix = "ns",
         namespace = "ns: http://www.w3.org/2005/Atom"
+        namespace = "media: http://search.yahoo.com/mrss/"
     )]
     pub struct AtomEntry {
         #[yaserde(prefix="ns")]
         pub title: String,
         #[yaserde(prefix="ns")]
         pub id: String,
+        #[yaserde(rename="link", prefix="ns")]
+        pub links: Vec<AtomLink>,
         #[yaserde(prefix="ns")]
-        pub link: AtomEntryLink,
+        pub published: String,
         #[yaserde(prefix="ns")]
         pub updated: String,
+        #[yaserde(prefix="ns")]
+        pub summary: Option<AtomText>,
+        #[yaserde(prefix="ns")]
+        pub content: Option<AtomText>,
+        #[yaserde(prefix="ns")]
+        pub author: Option<AtomAuthor>,
+        #[yaserde(rename="group", prefix="media")]
+        pub media_group: Option<MediaGroup>,
+        #[yaserde(rename="thumbnail", prefix="media")]
+        pub media_thumbnails: Vec<MediaThumbnail>,
+        #[yaserde(rename="content", prefix="media")]
+        pub media_contents: Vec<MediaContent>,
+    }
+}
+
+// Declared outside of derive_item!, since yaserde can't expand optional text fields inside of it.
+#[derive(YaDeserialize, YaSerialize, Default, Debug, PartialEq)]
+#[yaserde(prefix = "ns", namespace = "ns: http://www.w3.org/2005/Atom")]
+pub struct AtomAuthor {
+    #[yaserde(prefix = "ns")]
+    pub name: String,
+    #[yaserde(prefix = "ns")]
+    pub uri: Option<String>,
+}
+
+derive_item! {
+    #[yaserde(
+        prefix = "ns",
+        namespace = "ns: http://www.w3.org/2005/Atom"
+    )]
+    pub struct AtomText {
+        #[yaserde(attribute, rename="type")]
+        pub content_type: String,
+        #[yaserde(text)]
+        pub value: String,
     }
 }
 
@@ -59,13 +103,37 @@ derive_item! {
 
 derive_item! {
     #[yaserde(
-        prefix = "ns",
-        namespace = "ns: http://www.w3.org/2005/Atom"
+        prefix = "media",
+        namespace = "media: http://search.yahoo.com/mrss/"
     )]
-    pub struct AtomEntryLink {
-        #[yaserde(attribute, prefix="ns")]
-        pub href: String,
-        #[yaserde(attribute, prefix="ns")]
-        pub rel: String,
+    pub struct MediaGroup {
+        #[yaserde(rename="thumbnail", prefix="media")]
+        pub thumbnails: Vec<MediaThumbnail>,
+        #[yaserde(rename="content", prefix="media")]
+        pub contents: Vec<MediaContent>,
+    }
+}
+
+derive_item! {
+    #[yaserde(
+        prefix = "media",
+        namespace = "media: http://search.yahoo.com/mrss/"
+    )]
+    pub struct MediaThumbnail {
+        #[yaserde(attribute)]
+        pub url: String,
+    }
+}
+
+derive_item! {
+    #[yaserde(
+        prefix = "media",
+        namespace = "media: http://search.yahoo.com/mrss/"
+    )]
+    pub struct MediaContent {
+        #[yaserde(attribute)]
+        pub url: String,
+        #[yaserde(attribute, rename="type")]
+        pub media_type: String,
     }
 }
@@ -1,69 +1,131 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use chrono::prelude::*;
 use url::Url;
 use yaserde::de::from_reader;
 
 use super::{
-    atom::AtomFeed, model::Entry, serialization::invalid_xml_structure, Feed, FeedDeserializer,
+    atom::{AtomEntry, AtomFeed},
+    dates::{parse_date, parse_date_or},
+    model::{Author, Entry, Media, MediaKind, UNKNOWN_AUTHOR},
+    serialization::{entry_id, feed_link, invalid_xml_structure, partition_entries, ParsedFeed},
+    Feed, FeedDeserializer,
 };
 
 pub struct AtomDeserializer;
 
 impl FeedDeserializer for AtomDeserializer {
-    fn parse_feed_from_bytes(&self, bytes: &[u8]) -> Result<Feed> {
+    fn parse_feed_with_warnings(&self, bytes: &[u8], source_url: &Url) -> Result<ParsedFeed> {
         let mut feed: AtomFeed = from_reader(bytes).map_err(invalid_xml_structure)?;
 
-        let href = &feed
+        let href = feed
             .links
             .iter()
             .find(|li| li.link_type == "application/atom+xml")
-            .ok_or_else(|| {
-                invalid_xml_structure(
-                    "Could not find link.type='application/atom+xml' in atom feed".into(),
-                )
-            })?
-            .href;
-        let link = Url::parse(href).map_err(|err| {
-            invalid_xml_structure(format!("Failed to parse 'link.href' in atom feed: {}", err))
-        })?;
-
-        let entries = std::mem::replace(&mut feed.entries, vec![]);
+            .or_else(|| feed.links.iter().find(|li| li.rel == "self"))
+            .map(|li| li.href.as_str());
+        let link = feed_link(href, source_url)?;
+
+        // Entries without a usable date are treated as new as the feed itself.
+        let feed_date = parse_date(&feed.updated).unwrap_or_else(Utc::now);
+
+        let entries = std::mem::take(&mut feed.entries);
         let entry_results: Vec<Result<Entry>> = entries
             .into_iter()
             .map(|ae| {
-                let updated = DateTime::parse_from_rfc3339(&ae.updated).map_err(|e| {
-                    invalid_xml_structure(format!(
-                        "Failed to parse 'updated' element: {}",
-                        e.to_string()
-                    ))
-                })?;
+                let updated = parse_date_or(&ae.updated, feed_date);
+                // published is optional in atom, and the entry was at the latest published when it was updated.
+                let published = parse_date_or(&ae.published, updated);
+                let link = ae
+                    .links
+                    .iter()
+                    .find(|li| li.rel.is_empty() || li.rel == "alternate")
+                    .map(|li| li.href.clone())
+                    .ok_or_else(|| {
+                        invalid_xml_structure(
+                            "Could not find an alternate link in atom entry".into(),
+                        )
+                    })?;
+                let media = atom_entry_media(&ae);
+                // Prefer the richer html content, since that is what we emit as content again.
+                let summary = ae
+                    .content
+                    .filter(|c| c.content_type != "xhtml")
+                    .or(ae.summary)
+                    .map(|text| text.value)
+                    .filter(|value| !value.is_empty())
+                    .unwrap_or_else(|| ae.title.clone());
                 let e = Entry {
-                    id: ae.link.href.clone(),
-                    summary: ae.title.clone(),
-                    link: ae.link.href,
+                    id: entry_id(&ae.id, &link, &ae.title),
+                    summary,
+                    author: ae.author.map(|author| Author {
+                        name: author.name,
+                        uri: author.uri,
+                    }),
+                    link,
                     title: ae.title,
-                    updated: updated.into(),
+                    updated,
+                    published,
+                    sources: vec![],
+                    media,
                 };
                 Ok(e)
             })
             .collect();
-        let mut entries = vec![];
-        for e in entry_results {
-            entries.push(e.context("Failed to deserialize an atom feed entry")?);
-        }
+        let (entries, warnings) = partition_entries(entry_results);
 
-        Ok(Feed {
-            author_name: "Unknown".into(),
-            id: feed.title.clone(),
-            link: link,
-            title: feed.title,
-            entries: entries,
+        Ok(ParsedFeed {
+            feed: Feed {
+                author_name: feed
+                    .author
+                    .map(|author| author.name)
+                    .unwrap_or_else(|| UNKNOWN_AUTHOR.into()),
+                id: feed.title.clone(),
+                link,
+                title: feed.title,
+                entries,
+            },
+            warnings,
         })
     }
 }
 
+fn atom_entry_media(ae: &AtomEntry) -> Vec<Media> {
+    let enclosures = ae
+        .links
+        .iter()
+        .filter(|li| li.rel == "enclosure")
+        .map(|li| (li.href.clone(), li.link_type.clone()));
+    let contents = ae
+        .media_group
+        .iter()
+        .flat_map(|group| group.contents.iter())
+        .chain(ae.media_contents.iter())
+        .map(|content| (content.url.clone(), content.media_type.clone()));
+    let thumbnails = ae
+        .media_group
+        .iter()
+        .flat_map(|group| group.thumbnails.iter())
+        .chain(ae.media_thumbnails.iter())
+        .map(|thumbnail| Media {
+            kind: MediaKind::Thumbnail,
+            url: thumbnail.url.clone(),
+            media_type: None,
+        });
+
+    enclosures
+        .chain(contents)
+        .map(|(url, media_type)| Media {
+            kind: MediaKind::Enclosure,
+            url,
+            media_type: Some(media_type).filter(|t| !t.is_empty()),
+        })
+        .chain(thumbnails)
+        .collect()
+}
+
 #[cfg(test)]
 mod parser_tests {
+    use super::super::serialization::example_source_url;
     use super::*;
 
     #[test]
@@ -73,7 +135,7 @@ mod parser_tests {
         let deserializer = AtomDeserializer {};
 
         let feed = deserializer
-            .parse_feed_from_bytes(feed_str.as_bytes())
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
             .unwrap();
         let expected = Feed {
             author_name: "Unknown".into(),
@@ -87,6 +149,21 @@ mod parser_tests {
         assert_eq!(expected, feed);
     }
 
+    #[test]
+    fn feed_without_self_link_falls_back_to_the_source_url() {
+        let feed_str = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+            <link rel="alternate" type="text/html" href="https://example.com/"/>
+            <title>No self link</title>
+        </feed>"#;
+        let deserializer = AtomDeserializer {};
+
+        let feed = deserializer
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
+
+        assert_eq!(example_source_url(), feed.link);
+    }
+
     #[test]
     fn feed_with_one_entry_can_be_parsed() {
         let feed_str = std::fs::read_to_string("src/res/example_one_element_atom_feed.xml")
@@ -94,21 +171,38 @@ mod parser_tests {
         let deserializer = AtomDeserializer {};
 
         let feed = deserializer
-            .parse_feed_from_bytes(feed_str.as_bytes())
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
             .unwrap();
 
         let expected = Feed {
             author_name: "Unknown".into(),
             entries: vec![Entry {
                 title: String::from("SmallAnt makes a ✨𝘧𝘳𝘪𝘦𝘯𝘥✨"),
-                id: "http://invidious.privacy.qvarford.net/watch?v=be8ZARHsjmc".into(),
+                id: "yt:video:be8ZARHsjmc".into(),
                 link: "http://invidious.privacy.qvarford.net/watch?v=be8ZARHsjmc"
                     .parse()
                     .unwrap(),
                 summary: String::from("SmallAnt makes a ✨𝘧𝘳𝘪𝘦𝘯𝘥✨"),
+                author: Some(Author {
+                    name: "SmallAnt Clips".into(),
+                    uri: Some(
+                        "http://invidious.privacy.qvarford.net/channel/UCnyP4sbJVIU9JqHz4l6oQZw"
+                            .into(),
+                    ),
+                }),
+                sources: vec![],
+                media: vec![Media {
+                    kind: MediaKind::Thumbnail,
+                    url: "http://invidious.privacy.qvarford.net/vi/be8ZARHsjmc/mqdefault.jpg"
+                        .into(),
+                    media_type: None,
+                }],
                 updated: DateTime::parse_from_rfc3339("2022-03-22T07:26:01+00:00")
                     .unwrap()
                     .into(),
+                published: DateTime::parse_from_rfc3339("2022-03-22T07:00:09+00:00")
+                    .unwrap()
+                    .into(),
             }],
             id: "Example feed".into(),
             link: "https://invidious.privacy.qvarford.net/feed/private?token=something"
@@ -118,4 +212,52 @@ mod parser_tests {
         };
         assert_eq!(expected, feed);
     }
+
+    #[test]
+    fn serialized_summary_can_be_parsed_again() {
+        let feed = Feed {
+            author_name: "Unknown".into(),
+            entries: vec![Entry {
+                title: "A post".into(),
+                id: "https://example.com/posts/1".into(),
+                link: "https://example.com/posts/1".into(),
+                summary: r#"<p>Some <b>bold</b> text</p><img src="https://example.com/a.png" />"#
+                    .into(),
+                updated: DateTime::parse_from_rfc3339("2022-03-22T07:26:01+00:00")
+                    .unwrap()
+                    .into(),
+                published: DateTime::parse_from_rfc3339("2022-03-22T07:00:09+00:00")
+                    .unwrap()
+                    .into(),
+                author: Some(Author {
+                    name: "Example author".into(),
+                    uri: None,
+                }),
+                sources: vec![],
+                media: vec![
+                    Media {
+                        kind: MediaKind::Enclosure,
+                        url: "https://example.com/a.mp4".into(),
+                        media_type: Some("video/mp4".into()),
+                    },
+                    Media {
+                        kind: MediaKind::Thumbnail,
+                        url: "https://example.com/a.png".into(),
+                        media_type: None,
+                    },
+                ],
+            }],
+            id: "Example feed".into(),
+            link: "https://example.com/atom.xml".try_into().unwrap(),
+            title: "Example feed".into(),
+        };
+
+        let feed_str = feed.clone().serialize_to_string().unwrap();
+        let parsed = AtomDeserializer {}
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
+
+        assert!(feed_str.contains(r#"<ns:summary type="text">Some bold text</ns:summary>"#));
+        assert_eq!(feed.entries, parsed.entries);
+    }
 }
@@ -0,0 +1,181 @@
+use chrono::prelude::*;
+use log::warn;
+
+// Timezone abbreviations seen in real feeds that chrono's rfc 2822 parser doesn't know about.
+const NAMED_OFFSETS: [(&str, &str); 16] = [
+    ("Z", "+0000"),
+    ("UTC", "+0000"),
+    ("UT", "+0000"),
+    ("GMT", "+0000"),
+    ("EST", "-0500"),
+    ("EDT", "-0400"),
+    ("CST", "-0600"),
+    ("CDT", "-0500"),
+    ("MST", "-0700"),
+    ("MDT", "-0600"),
+    ("PST", "-0800"),
+    ("PDT", "-0700"),
+    ("BST", "+0100"),
+    ("CET", "+0100"),
+    ("CEST", "+0200"),
+    ("JST", "+0900"),
+];
+
+const ZONED_FORMATS: [&str; 4] = [
+    "%d %b %Y %H:%M:%S %z",
+    "%d %b %Y %H:%M %z",
+    "%d %B %Y %H:%M:%S %z",
+    "%d %B %Y %H:%M %z",
+];
+
+const ISO_ZONED_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%d %H:%M:%S%.f%z"];
+
+const ISO_NAIVE_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+/// Parse a date the way it's found in the wild, which is rfc 2822 or rfc 3339 most of the time.
+/// Dates without a timezone are assumed to be in UTC.
+pub fn parse_date(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+
+    DateTime::parse_from_rfc2822(s)
+        .or_else(|_| DateTime::parse_from_rfc3339(s))
+        .ok()
+        .or_else(|| parse_lenient_rfc2822(s))
+        .or_else(|| parse_iso(s))
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// Like parse_date, but a missing or unparseable date is replaced with the fallback,
+/// so that a single broken entry doesn't make the whole feed unusable.
+pub fn parse_date_or(s: &str, fallback: DateTime<Utc>) -> DateTime<Utc> {
+    if s.trim().is_empty() {
+        return fallback;
+    }
+    parse_date(s).unwrap_or_else(|| {
+        warn!("Could not parse date '{s}', using {fallback} instead.");
+        fallback
+    })
+}
+
+fn parse_lenient_rfc2822(s: &str) -> Option<DateTime<FixedOffset>> {
+    // Some feeds use full or incorrect weekday names, and the weekday is redundant anyway.
+    let s = s.split_once(',').map(|(_, rest)| rest.trim()).unwrap_or(s);
+    let (rest, zone) = s.rsplit_once(' ')?;
+
+    let normalized = if let Some((_, offset)) = NAMED_OFFSETS
+        .iter()
+        .find(|(name, _)| zone.eq_ignore_ascii_case(name))
+    {
+        format!("{rest} {offset}")
+    } else if zone.starts_with('+') || zone.starts_with('-') {
+        s.into()
+    } else {
+        format!("{s} +0000")
+    };
+
+    ZONED_FORMATS
+        .iter()
+        .find_map(|format| DateTime::parse_from_str(&normalized, format).ok())
+}
+
+fn parse_iso(s: &str) -> Option<DateTime<FixedOffset>> {
+    let utc = FixedOffset::east(0);
+    let s = match s.strip_suffix('Z') {
+        Some(rest) => format!("{rest}+00:00"),
+        None => s.into(),
+    };
+    let s = s.as_str();
+    ISO_ZONED_FORMATS
+        .iter()
+        .find_map(|format| DateTime::parse_from_str(s, format).ok())
+        .or_else(|| {
+            ISO_NAIVE_FORMATS
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+                .or_else(|| {
+                    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                        .ok()
+                        .map(|date| date.and_hms(0, 0, 0))
+                })
+                .map(|naive| utc.from_utc_datetime(&naive))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn utc(s: &str) -> Option<DateTime<Utc>> {
+        Some(DateTime::parse_from_rfc3339(s).unwrap().into())
+    }
+
+    #[test]
+    fn strict_dates_can_be_parsed() {
+        assert_eq!(
+            parse_date("Sat, 09 Apr 2022 01:23:14 GMT"),
+            utc("2022-04-09T01:23:14+00:00")
+        );
+        assert_eq!(
+            parse_date("2022-03-22T07:26:01+01:00"),
+            utc("2022-03-22T06:26:01+00:00")
+        );
+    }
+
+    #[test]
+    fn malformed_rfc2822_dates_can_be_parsed() {
+        let cases = [
+            ("09 Apr 2022 01:23:14 GMT", "2022-04-09T01:23:14+00:00"),
+            (
+                "Saturday, 09 Apr 2022 01:23:14 GMT",
+                "2022-04-09T01:23:14+00:00",
+            ),
+            ("Mon, 09 Apr 2022 01:23:14 GMT", "2022-04-09T01:23:14+00:00"),
+            ("Sat, 09 Apr 2022 01:23:14 EST", "2022-04-09T06:23:14+00:00"),
+            (
+                "Sat, 09 Apr 2022 01:23:14 CEST",
+                "2022-04-08T23:23:14+00:00",
+            ),
+            ("Sat, 09 Apr 2022 01:23:14 UTC", "2022-04-09T01:23:14+00:00"),
+            ("Sat, 09 Apr 2022 01:23:14", "2022-04-09T01:23:14+00:00"),
+            (
+                "Sat, 09 April 2022 01:23 +0200",
+                "2022-04-08T23:23:00+00:00",
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_date(input), utc(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn iso_dates_can_be_parsed() {
+        let cases = [
+            ("2022-04-09T01:23:14Z", "2022-04-09T01:23:14+00:00"),
+            ("2022-04-09T01:23:14.123Z", "2022-04-09T01:23:14.123+00:00"),
+            ("2022-04-09 01:23:14+0200", "2022-04-08T23:23:14+00:00"),
+            ("2022-04-09T01:23:14", "2022-04-09T01:23:14+00:00"),
+            ("2022-04-09 01:23", "2022-04-09T01:23:00+00:00"),
+            ("2022-04-09", "2022-04-09T00:00:00+00:00"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_date(input), utc(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn missing_or_invalid_dates_use_the_fallback() {
+        let fallback = utc("2022-01-01T00:00:00+00:00").unwrap();
+
+        assert_eq!(parse_date_or("", fallback), fallback);
+        assert_eq!(parse_date_or("  ", fallback), fallback);
+        assert_eq!(parse_date_or("yesterday", fallback), fallback);
+    }
+}
@@ -0,0 +1,272 @@
+use anyhow::{Context, Error, Result};
+use derive_more::Display;
+use url::Url;
+
+use super::serialization::ParsedFeed;
+use super::FeedDeserializer;
+
+#[derive(Display, Debug, PartialEq, Clone, Copy)]
+pub enum FeedFormat {
+    #[display(fmt = "rss")]
+    Rss,
+    #[display(fmt = "atom")]
+    Atom,
+    #[display(fmt = "rss 1.0 (rdf)")]
+    Rdf,
+}
+
+/// Picks a deserializer based on the root element of the document, or the content type when the root element
+/// doesn't name a format, instead of trying every deserializer and only reporting the last error.
+pub struct DetectingDeserializer {
+    rss: Box<dyn FeedDeserializer>,
+    atom: Box<dyn FeedDeserializer>,
+    rdf: Box<dyn FeedDeserializer>,
+}
+
+impl DetectingDeserializer {
+    pub fn new(
+        rss: Box<dyn FeedDeserializer>,
+        atom: Box<dyn FeedDeserializer>,
+        rdf: Box<dyn FeedDeserializer>,
+    ) -> DetectingDeserializer {
+        DetectingDeserializer { rss, atom, rdf }
+    }
+
+    fn deserializer(&self, format: FeedFormat) -> &dyn FeedDeserializer {
+        match format {
+            FeedFormat::Rss => self.rss.as_ref(),
+            FeedFormat::Atom => self.atom.as_ref(),
+            FeedFormat::Rdf => self.rdf.as_ref(),
+        }
+    }
+}
+
+impl FeedDeserializer for DetectingDeserializer {
+    fn parse_feed_with_warnings(&self, bytes: &[u8], source_url: &Url) -> Result<ParsedFeed> {
+        self.parse_feed_with_content_type(bytes, source_url, None)
+    }
+
+    fn parse_feed_with_content_type(
+        &self,
+        bytes: &[u8],
+        source_url: &Url,
+        content_type: Option<&str>,
+    ) -> Result<ParsedFeed> {
+        let content_type_description = content_type
+            .map(|ct| format!("content type {}", ct))
+            .unwrap_or_else(|| "no content type".into());
+        let root = root_element_name(bytes);
+        let detected = match root.as_deref().and_then(detect_format) {
+            Some(format) => Some((
+                format,
+                format!("the <{}> root element", root.as_ref().unwrap()),
+            )),
+            // Some servers wrap feeds in unusual root elements, so trust what they say the document is.
+            None => content_type
+                .and_then(format_from_content_type)
+                .map(|format| (format, content_type_description.clone())),
+        };
+        let (format, detected_from) = match (detected, root) {
+            (Some(detected), _) => detected,
+            (None, _) if content_type.is_some_and(is_html) => {
+                return Err(Error::msg(format!(
+                    "Got an html page instead of a feed ({})",
+                    content_type_description
+                )))
+            }
+            (None, Some(root)) => {
+                return Err(Error::msg(format!(
+                    "Unrecognized root element <{}> ({}), expected <rss>, <feed> or <rdf:RDF>",
+                    root, content_type_description
+                )))
+            }
+            (None, None) => {
+                return Err(Error::msg(format!(
+                    "Could not find a root element in the document ({})",
+                    content_type_description
+                )))
+            }
+        };
+
+        self.deserializer(format)
+            .parse_feed_with_warnings(bytes, source_url)
+            .with_context(|| {
+                format!(
+                    "Failed to parse the document as {} after detecting it from {} ({})",
+                    format, detected_from, content_type_description
+                )
+            })
+    }
+}
+
+// The media type without parameters like charset, which are irrelevant for picking a parser.
+fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
+pub fn format_from_content_type(content_type: &str) -> Option<FeedFormat> {
+    match media_type(content_type).as_str() {
+        "application/rss+xml" => Some(FeedFormat::Rss),
+        "application/atom+xml" => Some(FeedFormat::Atom),
+        "application/rdf+xml" => Some(FeedFormat::Rdf),
+        _ => None,
+    }
+}
+
+fn is_html(content_type: &str) -> bool {
+    matches!(
+        media_type(content_type).as_str(),
+        "text/html" | "application/xhtml+xml"
+    )
+}
+
+pub fn detect_format(root_element_name: &str) -> Option<FeedFormat> {
+    let local_name = root_element_name
+        .rsplit_once(':')
+        .map(|(_, local_name)| local_name)
+        .unwrap_or(root_element_name);
+    match local_name {
+        "rss" => Some(FeedFormat::Rss),
+        "feed" => Some(FeedFormat::Atom),
+        "RDF" => Some(FeedFormat::Rdf),
+        _ => None,
+    }
+}
+
+/// Find the name of the first element in an xml document,
+/// skipping the xml declaration, processing instructions, comments and doctypes.
+pub fn root_element_name(bytes: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut rest = text.trim_start_matches('\u{feff}');
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = &after[after.find("-->")? + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = &rest[rest.find('>')? + 1..];
+        } else {
+            let after = rest.strip_prefix('<')?;
+            let end = after
+                .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                .unwrap_or(after.len());
+            return Some(after[..end].into()).filter(|name: &String| !name.is_empty());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::serialization::example_source_url;
+    use super::*;
+    use crate::feed::default_feed_deserializer;
+
+    #[test]
+    fn root_element_is_found_after_prolog() {
+        let document = r#"<?xml version="1.0"?>
+            <!-- generated -->
+            <!DOCTYPE rdf:RDF>
+            <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"></rdf:RDF>"#;
+
+        assert_eq!(
+            root_element_name(document.as_bytes()),
+            Some("rdf:RDF".into())
+        );
+    }
+
+    #[test]
+    fn format_is_detected_from_root_element() {
+        assert_eq!(detect_format("rss"), Some(FeedFormat::Rss));
+        assert_eq!(detect_format("feed"), Some(FeedFormat::Atom));
+        assert_eq!(detect_format("ns:feed"), Some(FeedFormat::Atom));
+        assert_eq!(detect_format("rdf:RDF"), Some(FeedFormat::Rdf));
+        assert_eq!(detect_format("html"), None);
+    }
+
+    #[test]
+    fn every_supported_format_is_parsed() {
+        let deserializer = default_feed_deserializer();
+        for file in [
+            "src/res/example_one_element_rss_feed.xml",
+            "src/res/example_one_element_atom_feed.xml",
+            "src/res/example_rdf_feed.xml",
+        ] {
+            let bytes = std::fs::read(file).expect("Expected example file to exist.");
+            let feed = deserializer
+                .parse_feed_from_bytes(&bytes, &example_source_url())
+                .unwrap();
+            assert!(!feed.entries.is_empty(), "{}", file);
+        }
+    }
+
+    #[test]
+    fn errors_name_the_detected_format_and_content_type() {
+        let deserializer = default_feed_deserializer();
+
+        let error = deserializer
+            .parse_feed_with_content_type(
+                b"<html><body>Rate limited</body></html>",
+                &example_source_url(),
+                Some("text/html"),
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Got an html page instead of a feed (content type text/html)"
+        );
+
+        let error = deserializer
+            .parse_feed_with_content_type(
+                b"<document />",
+                &example_source_url(),
+                Some("application/xml"),
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Unrecognized root element <document> (content type application/xml), expected <rss>, <feed> or <rdf:RDF>"
+        );
+    }
+
+    #[test]
+    fn content_type_decides_when_the_root_element_does_not() {
+        assert_eq!(
+            format_from_content_type("application/atom+xml; charset=utf-8"),
+            Some(FeedFormat::Atom)
+        );
+        assert_eq!(format_from_content_type("text/xml"), None);
+
+        let atom = std::fs::read_to_string("src/res/example_one_element_atom_feed.xml")
+            .expect("Expected example file to exist.")
+            .replace("<feed ", "<atomfeed ")
+            .replace("</feed>", "</atomfeed>");
+        let feed = default_feed_deserializer()
+            .parse_feed_with_content_type(
+                atom.as_bytes(),
+                &example_source_url(),
+                Some("application/atom+xml"),
+            )
+            .unwrap();
+
+        assert!(!feed.feed.entries.is_empty());
+    }
+
+    #[test]
+    fn errors_name_the_parser_that_failed() {
+        let deserializer = default_feed_deserializer();
+
+        let error = deserializer
+            .parse_feed_from_bytes(b"<feed><title>Unclosed title</feed>", &example_source_url())
+            .unwrap_err();
+
+        assert!(error.to_string().starts_with(
+            "Failed to parse the document as atom after detecting it from the <feed> root element"
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::{Context, Error, Result};
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::convert::TryFrom;
+
+use super::model::Entry;
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterField {
+    Title,
+    Summary,
+    Link,
+    Author,
+}
+
+const ALL_FIELDS: [FilterField; 4] = [
+    FilterField::Title,
+    FilterField::Summary,
+    FilterField::Link,
+    FilterField::Author,
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FilterAction {
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Matches when the field contains the keyword, ignoring case.
+    Keyword(String),
+    Regex(Regex),
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        match (self, other) {
+            (Pattern::Keyword(a), Pattern::Keyword(b)) => a == b,
+            (Pattern::Regex(a), Pattern::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(try_from = "FilterRuleFormat")]
+pub struct FilterRule {
+    pub action: FilterAction,
+    pub pattern: Pattern,
+    pub fields: Vec<FilterField>,
+}
+
+// A rule is written as a table with exactly one of include, exclude, include_regex or exclude_regex.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilterRuleFormat {
+    include: Option<String>,
+    exclude: Option<String>,
+    include_regex: Option<String>,
+    exclude_regex: Option<String>,
+    #[serde(default)]
+    fields: Vec<FilterField>,
+}
+
+impl TryFrom<FilterRuleFormat> for FilterRule {
+    type Error = Error;
+
+    fn try_from(format: FilterRuleFormat) -> Result<FilterRule> {
+        let (action, pattern) = match (
+            format.include,
+            format.exclude,
+            format.include_regex,
+            format.exclude_regex,
+        ) {
+            (Some(keyword), None, None, None) => (FilterAction::Include, keyword_pattern(keyword)),
+            (None, Some(keyword), None, None) => (FilterAction::Exclude, keyword_pattern(keyword)),
+            (None, None, Some(regex), None) => (FilterAction::Include, regex_pattern(&regex)?),
+            (None, None, None, Some(regex)) => (FilterAction::Exclude, regex_pattern(&regex)?),
+            _ => return Err(Error::msg(
+                "A filter needs exactly one of include, exclude, include_regex or exclude_regex",
+            )),
+        };
+        let fields = if format.fields.is_empty() {
+            ALL_FIELDS.to_vec()
+        } else {
+            format.fields
+        };
+        Ok(FilterRule {
+            action,
+            pattern,
+            fields,
+        })
+    }
+}
+
+fn keyword_pattern(keyword: String) -> Pattern {
+    Pattern::Keyword(keyword.to_lowercase())
+}
+
+fn regex_pattern(regex: &str) -> Result<Pattern> {
+    Regex::new(regex)
+        .map(Pattern::Regex)
+        .with_context(|| format!("Invalid filter regex {}", regex))
+}
+
+impl FilterRule {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.fields.iter().any(|field| {
+            let value = match field {
+                FilterField::Title => entry.title.as_str(),
+                FilterField::Summary => entry.summary.as_str(),
+                FilterField::Link => entry.link.as_str(),
+                FilterField::Author => entry
+                    .author
+                    .as_ref()
+                    .map(|author| author.name.as_str())
+                    .unwrap_or_default(),
+            };
+            match &self.pattern {
+                Pattern::Keyword(keyword) => value.to_lowercase().contains(keyword),
+                Pattern::Regex(regex) => regex.is_match(value),
+            }
+        })
+    }
+}
+
+/// An entry is kept when it matches none of the exclude rules,
+/// and at least one of the include rules if there are any.
+pub fn is_included(entry: &Entry, rules: &[FilterRule]) -> bool {
+    let mut includes = rules
+        .iter()
+        .filter(|rule| rule.action == FilterAction::Include)
+        .peekable();
+    let included = includes.peek().is_none() || includes.any(|rule| rule.matches(entry));
+    included
+        && !rules
+            .iter()
+            .filter(|rule| rule.action == FilterAction::Exclude)
+            .any(|rule| rule.matches(entry))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::feed::model::Author;
+
+    fn entry(title: &str, author: &str) -> Entry {
+        Entry {
+            title: title.into(),
+            author: Some(Author {
+                name: author.into(),
+                uri: None,
+            }),
+            ..Entry::for_test("https://example.com/posts/1")
+        }
+    }
+
+    fn rules(toml: &str) -> Result<Vec<FilterRule>, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct Rules {
+            filters: Vec<FilterRule>,
+        }
+        toml::from_str::<Rules>(toml).map(|rules| rules.filters)
+    }
+
+    #[test]
+    fn excluded_keywords_are_filtered_out() {
+        let rules = rules(r#"filters = [{ exclude = "Giveaway" }]"#).unwrap();
+
+        assert!(!is_included(&entry("Big GIVEAWAY today", "Bob"), &rules));
+        assert!(is_included(&entry("New video", "Bob"), &rules));
+    }
+
+    #[test]
+    fn only_entries_matching_an_include_rule_are_kept() {
+        let rules = rules(
+            r#"filters = [
+                { include_regex = "(?i)^speedrun", fields = ["title"] },
+                { include = "alice", fields = ["author"] },
+                { exclude_regex = "\\bRT\\b" },
+            ]"#,
+        )
+        .unwrap();
+
+        assert!(is_included(&entry("Speedrun attempts", "Bob"), &rules));
+        assert!(is_included(&entry("Just chatting", "Alice"), &rules));
+        assert!(!is_included(&entry("Just chatting", "Bob"), &rules));
+        assert!(!is_included(&entry("RT speedrun", "Alice"), &rules));
+    }
+
+    #[test]
+    fn rules_need_exactly_one_pattern() {
+        assert!(rules(r#"filters = [{ fields = ["title"] }]"#).is_err());
+        assert!(rules(r#"filters = [{ include = "a", exclude = "b" }]"#).is_err());
+        assert!(rules(r#"filters = [{ exclude_regex = "(" }]"#).is_err());
+    }
+}
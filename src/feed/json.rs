@@ -0,0 +1,40 @@
+use serde_derive::Serialize;
+
+pub const JSON_FEED_VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct JsonFeed {
+    pub version: String,
+    pub title: String,
+    pub feed_url: String,
+    pub items: Vec<JsonFeedItem>,
+}
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct JsonFeedItem {
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    pub content_html: String,
+    pub date_published: String,
+    pub date_modified: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<JsonFeedAttachment>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<JsonFeedAuthor>,
+}
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct JsonFeedAttachment {
+    pub url: String,
+    pub mime_type: String,
+}
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct JsonFeedAuthor {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
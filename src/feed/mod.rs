@@ -1,20 +1,28 @@
 pub mod atom;
 pub mod atom_serialization;
-pub mod fallback_serialization;
+pub mod dates;
+pub mod detecting_serialization;
+pub mod filter;
+pub mod json;
 pub mod model;
+pub mod rdf_serialization;
+pub mod rewrite;
+pub mod rss;
 pub mod rss_serialization;
 pub mod serialization;
 
 use self::atom_serialization::AtomDeserializer;
-use self::fallback_serialization::FallbackDeserializer;
+use self::detecting_serialization::DetectingDeserializer;
+use self::rdf_serialization::RdfDeserializer;
 use self::rss_serialization::RssDeserializer;
 
 pub use self::model::{merge_feeds, Feed};
 pub use self::serialization::FeedDeserializer;
 
 pub fn default_feed_deserializer() -> impl FeedDeserializer {
-    FallbackDeserializer::new(vec![
+    DetectingDeserializer::new(
         Box::new(RssDeserializer {}),
         Box::new(AtomDeserializer {}),
-    ])
+        Box::new(RdfDeserializer {}),
+    )
 }
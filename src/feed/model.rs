@@ -1,13 +1,42 @@
 use chrono::prelude::*;
+use chrono::Duration;
+use serde_derive::Deserialize;
+use std::collections::{HashMap, HashSet};
 use url::Url;
 
+pub const UNKNOWN_AUTHOR: &str = "Unknown";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Author {
+    pub name: String,
+    pub uri: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum MediaKind {
+    Enclosure,
+    Thumbnail,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Media {
+    pub kind: MediaKind,
+    pub url: String,
+    pub media_type: Option<String>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Entry {
     pub title: String,
     pub link: String,
     pub id: String,
     pub updated: DateTime<Utc>,
+    pub published: DateTime<Utc>,
     pub summary: String,
+    pub author: Option<Author>,
+    pub media: Vec<Media>,
+    /// Links of the feeds the entry was merged from, more than one if it was a duplicate.
+    pub sources: Vec<String>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -19,26 +48,224 @@ pub struct Feed {
     pub entries: Vec<Entry>,
 }
 
-pub fn merge_feeds(id: String, link: Url, feeds: Vec<Feed>) -> Feed {
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    // Some sources bump the updated date of old entries, so published is a more stable order.
+    #[default]
+    Published,
+    Updated,
+}
+
+impl SortKey {
+    fn date(&self, entry: &Entry) -> DateTime<Utc> {
+        match self {
+            SortKey::Published => entry.published,
+            SortKey::Updated => entry.updated,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupStrategy {
+    #[default]
+    Off,
+    /// Entries with the same globally unique id or the same normalized link are duplicates.
+    Link,
+    /// Like link, but entries with nearly the same title are duplicates as well.
+    Title,
+}
+
+// The share of words two titles need to have in common to be considered the same.
+const TITLE_SIMILARITY_THRESHOLD: f64 = 0.8;
+
+/// How the entries of the feeds in a category are combined.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct MergeOptions {
+    pub sort_by: SortKey,
+    pub dedup: DedupStrategy,
+    pub max_entries: Option<usize>,
+    /// Entries older than this, by the sort key, are left out.
+    pub max_age: Option<Duration>,
+    /// Only the latest entries of each feed are kept, so a single busy feed can't drown out the rest.
+    pub max_entries_per_source: Option<usize>,
+}
+
+pub fn merge_feeds(id: String, link: Url, feeds: Vec<Feed>, options: &MergeOptions) -> Feed {
     let titles = feeds
         .iter()
         .map(|feed| feed.title.as_str())
         .collect::<Vec<_>>()
         .join(" + ");
 
-    let mut entries: Vec<_> = feeds.into_iter().flat_map(|feed| feed.entries).collect();
-    // latest entry is first.
-    entries.sort_by(|a, b| b.updated.cmp(&a.updated));
+    let entries: Vec<_> = feeds
+        .into_iter()
+        .flat_map(|feed| {
+            let source = feed.link.to_string();
+            // Entries without an author are attributed to the feed they came from,
+            // so that it's still possible to tell the sources apart after merging.
+            let feed_author = Author {
+                name: if feed.author_name == UNKNOWN_AUTHOR {
+                    feed.title
+                } else {
+                    feed.author_name
+                },
+                uri: Some(feed.link.into()),
+            };
+            let mut entries = feed.entries;
+            if let Some(max_entries_per_source) = options.max_entries_per_source {
+                sort_latest_first(&mut entries, options.sort_by);
+                entries.truncate(max_entries_per_source);
+            }
+            entries.into_iter().map(move |mut entry| {
+                entry.author = entry.author.or_else(|| Some(feed_author.clone()));
+                entry.sources = vec![source.clone()];
+                entry
+            })
+        })
+        .collect();
+    let mut entries = dedup_entries(entries, options.dedup);
+    if let Some(max_age) = options.max_age {
+        let oldest = Utc::now() - max_age;
+        entries.retain(|e| options.sort_by.date(e) >= oldest);
+    }
+    sort_latest_first(&mut entries, options.sort_by);
+    if let Some(max_entries) = options.max_entries {
+        entries.truncate(max_entries);
+    }
 
     Feed {
         title: titles,
-        author_name: "Unknown".into(),
+        author_name: UNKNOWN_AUTHOR.into(),
         id,
         link,
         entries,
     }
 }
 
+fn sort_latest_first(entries: &mut [Entry], sort_key: SortKey) {
+    entries.sort_by_key(|e| std::cmp::Reverse(sort_key.date(e)));
+}
+
+/// Keep the earliest published of each set of duplicates, and remember where the others came from.
+fn dedup_entries(mut entries: Vec<Entry>, dedup: DedupStrategy) -> Vec<Entry> {
+    if dedup == DedupStrategy::Off {
+        return entries;
+    }
+    entries.sort_by_key(|e| e.published);
+
+    let mut kept: Vec<Entry> = vec![];
+    // Indexes into kept by id and normalized link, so only titles have to be compared pairwise.
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut links: HashMap<String, usize> = HashMap::new();
+    let mut kept_title_words: Vec<HashSet<String>> = vec![];
+    for entry in entries {
+        let link = normalize_link(&entry.link);
+        let global_id = is_global_id(&entry.id);
+        let same_id = ids.get(&entry.id).filter(|_| global_id);
+        let same_link = [same_id, links.get(&link)]
+            .into_iter()
+            .flatten()
+            .min()
+            .copied();
+        let (similar_title, title_words) = if dedup == DedupStrategy::Title {
+            let title_words = title_words(&entry.title);
+            // Only entries kept before the one with the same link can be an earlier duplicate.
+            let candidates = &kept_title_words[..same_link.unwrap_or(kept.len())];
+            let similar_title = candidates.iter().position(|words| {
+                title_similarity(words, &title_words) >= TITLE_SIMILARITY_THRESHOLD
+            });
+            (similar_title, title_words)
+        } else {
+            (None, HashSet::new())
+        };
+
+        match similar_title.or(same_link) {
+            Some(index) => {
+                let original = &mut kept[index];
+                for source in entry.sources {
+                    if !original.sources.contains(&source) {
+                        original.sources.push(source);
+                    }
+                }
+            }
+            None => {
+                if global_id {
+                    ids.entry(entry.id.clone()).or_insert(kept.len());
+                }
+                links.entry(link).or_insert(kept.len());
+                kept_title_words.push(title_words);
+                kept.push(entry);
+            }
+        }
+    }
+    kept
+}
+
+/// Ids like urls, tag: or yt:video: uris are the same across feeds, unlike counters such as 1 or 2
+/// that only identify an entry within its own feed.
+fn is_global_id(id: &str) -> bool {
+    Url::parse(id).is_ok()
+}
+
+/// Strip the parts of a link that differ between mirrors of the same page,
+/// like the scheme, www, trailing slashes, fragments and tracking parameters.
+fn normalize_link(link: &str) -> String {
+    let url = match Url::parse(link.trim()) {
+        Ok(url) => url,
+        Err(_) => return link.trim().to_lowercase(),
+    };
+    let host = url.host_str().unwrap_or_default();
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let query: Vec<String> = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_"))
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    let mut normalized = format!("{}{}", host, url.path().trim_end_matches('/'));
+    if !query.is_empty() {
+        normalized = format!("{}?{}", normalized, query.join("&"));
+    }
+    normalized
+}
+
+fn title_words(title: &str) -> HashSet<String> {
+    title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// The share of distinct words in the titles that they have in common.
+fn title_similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let total = a.union(b).count();
+    if total == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / total as f64
+}
+
+#[cfg(test)]
+impl Entry {
+    /// An entry with the url as its id and link, and a fixed date, that tests fill in the rest of.
+    pub fn for_test(url: &str) -> Entry {
+        let date = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        Entry {
+            title: "".into(),
+            link: url.into(),
+            id: url.into(),
+            updated: date,
+            published: date,
+            summary: "".into(),
+            author: None,
+            sources: vec![],
+            media: vec![],
+        }
+    }
+}
+
 #[cfg(test)]
 mod parser_tests {
     use super::*;
@@ -54,21 +281,33 @@ mod parser_tests {
             entries: vec![
                 Entry {
                     id: "James Post 2".into(),
-                    link: "https://james.com/posts/2".try_into().unwrap(),
+                    link: "https://james.com/posts/2".into(),
                     title: "More thoughs on posting".into(),
                     summary: "Posting keeps being nice.".into(),
                     updated: DateTime::parse_from_rfc3339("2022-01-01T00:00:00+00:00")
                         .unwrap()
                         .into(),
+                    published: DateTime::parse_from_rfc3339("2022-01-01T00:00:00+00:00")
+                        .unwrap()
+                        .into(),
+                    author: None,
+                    sources: vec![],
+                    media: vec![],
                 },
                 Entry {
                     id: "James Post 1".into(),
-                    link: "https://james.com/posts/1".try_into().unwrap(),
+                    link: "https://james.com/posts/1".into(),
                     title: "Thinking about posting.".into(),
                     summary: "Posting is really nice.".into(),
                     updated: DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00")
                         .unwrap()
                         .into(),
+                    published: DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00")
+                        .unwrap()
+                        .into(),
+                    author: None,
+                    sources: vec![],
+                    media: vec![],
                 },
             ],
         };
@@ -80,21 +319,33 @@ mod parser_tests {
             entries: vec![
                 Entry {
                     id: "Jessica Post 2".into(),
-                    link: "https://jessica.com/posts/2".try_into().unwrap(),
+                    link: "https://jessica.com/posts/2".into(),
                     title: "I made a post two years ago.".into(),
                     summary: "Posting has not improved in two years.".into(),
                     updated: DateTime::parse_from_rfc3339("2021-01-01T00:00:00+00:00")
                         .unwrap()
                         .into(),
+                    published: DateTime::parse_from_rfc3339("2021-01-01T00:00:00+00:00")
+                        .unwrap()
+                        .into(),
+                    author: None,
+                    sources: vec![],
+                    media: vec![],
                 },
                 Entry {
                     id: "Jessica Post 1".into(),
-                    link: "https://jessica.com/posts/1".try_into().unwrap(),
+                    link: "https://jessica.com/posts/1".into(),
                     title: "Posting should improve.".into(),
                     summary: "It can only get better!".into(),
                     updated: DateTime::parse_from_rfc3339("2019-01-01T00:00:00+00:00")
                         .unwrap()
                         .into(),
+                    published: DateTime::parse_from_rfc3339("2019-01-01T00:00:00+00:00")
+                        .unwrap()
+                        .into(),
+                    author: None,
+                    sources: vec![],
+                    media: vec![],
                 },
             ],
         };
@@ -103,6 +354,10 @@ mod parser_tests {
             "Friends".into(),
             "https://friends.com/rss".try_into().unwrap(),
             vec![feed1, feed2],
+            &MergeOptions {
+                dedup: DedupStrategy::Off,
+                ..Default::default()
+            },
         );
 
         let post_ids: Vec<&str> = merged
@@ -122,4 +377,280 @@ mod parser_tests {
 
         assert_eq!(merged.title, "James Feed + Jessica Feed")
     }
+
+    #[test]
+    fn entries_can_be_sorted_by_published_or_updated() {
+        let entry = |id: &str, published: &str, updated: &str| Entry {
+            id: id.into(),
+            updated: DateTime::parse_from_rfc3339(updated).unwrap().into(),
+            published: DateTime::parse_from_rfc3339(published).unwrap().into(),
+            ..Entry::for_test("https://james.com/posts/1")
+        };
+        let feed = Feed {
+            author_name: "James".into(),
+            id: "James ID".into(),
+            link: "https://james.com/rss".try_into().unwrap(),
+            title: "James Feed".into(),
+            entries: vec![
+                entry(
+                    "Old post with new metadata",
+                    "2020-01-01T00:00:00+00:00",
+                    "2022-06-01T00:00:00+00:00",
+                ),
+                entry(
+                    "New post",
+                    "2022-01-01T00:00:00+00:00",
+                    "2022-01-01T00:00:00+00:00",
+                ),
+            ],
+        };
+        let merged_ids = |sort_key| -> Vec<String> {
+            merge_feeds(
+                "Friends".into(),
+                "https://friends.com/rss".try_into().unwrap(),
+                vec![feed.clone()],
+                &MergeOptions {
+                    sort_by: sort_key,
+                    dedup: DedupStrategy::Off,
+                    ..Default::default()
+                },
+            )
+            .entries
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect()
+        };
+
+        assert_eq!(
+            merged_ids(SortKey::Published),
+            vec!["New post", "Old post with new metadata"]
+        );
+        assert_eq!(
+            merged_ids(SortKey::Updated),
+            vec!["Old post with new metadata", "New post"]
+        );
+    }
+
+    #[test]
+    fn entries_without_author_are_attributed_to_their_feed() {
+        let entry = |id: &str, author: Option<Author>| Entry {
+            id: id.into(),
+            author,
+            ..Entry::for_test("https://james.com/posts/1")
+        };
+        let james = Author {
+            name: "James".into(),
+            uri: Some("https://james.com/about".into()),
+        };
+        let feed1 = Feed {
+            author_name: UNKNOWN_AUTHOR.into(),
+            id: "James ID".into(),
+            link: "https://james.com/rss".try_into().unwrap(),
+            title: "James Feed".into(),
+            entries: vec![entry("James Post", Some(james.clone()))],
+        };
+        let feed2 = Feed {
+            author_name: UNKNOWN_AUTHOR.into(),
+            id: "Jessica ID".into(),
+            link: "https://jessica.com/rss".try_into().unwrap(),
+            title: "Jessica Feed".into(),
+            entries: vec![entry("Jessica Post", None)],
+        };
+
+        let merged = merge_feeds(
+            "Friends".into(),
+            "https://friends.com/rss".try_into().unwrap(),
+            vec![feed1, feed2],
+            &MergeOptions {
+                dedup: DedupStrategy::Off,
+                ..Default::default()
+            },
+        );
+
+        let authors: Vec<Option<Author>> = merged
+            .entries
+            .into_iter()
+            .map(|entry| entry.author)
+            .collect();
+        assert_eq!(
+            authors,
+            vec![
+                Some(james),
+                Some(Author {
+                    name: "Jessica Feed".into(),
+                    uri: Some("https://jessica.com/rss".into())
+                })
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicates_keep_the_earliest_entry_and_all_sources() {
+        let entry = |id: &str, link: &str, title: &str, published: &str| Entry {
+            id: id.into(),
+            title: title.into(),
+            updated: DateTime::parse_from_rfc3339(published).unwrap().into(),
+            published: DateTime::parse_from_rfc3339(published).unwrap().into(),
+            ..Entry::for_test(link)
+        };
+        let feed = |link: &str, entries: Vec<Entry>| Feed {
+            author_name: UNKNOWN_AUTHOR.into(),
+            id: link.into(),
+            link: link.try_into().unwrap(),
+            title: link.into(),
+            entries,
+        };
+        let channel = feed(
+            "https://channel.com/rss",
+            vec![entry(
+                "yt:video:1",
+                "https://www.youtube.com/watch?v=1",
+                "Speedrunning the game, part 1",
+                "2022-01-02T00:00:00+00:00",
+            )],
+        );
+        let clips = feed(
+            "https://clips.com/rss",
+            vec![
+                entry(
+                    "clips:1",
+                    "https://youtube.com/watch?v=1&utm_source=clips",
+                    "Speedrunning the game, part 1",
+                    "2022-01-01T00:00:00+00:00",
+                ),
+                entry(
+                    "clips:2",
+                    "https://clips.com/2",
+                    "Speedrunning the game part 1!",
+                    "2022-01-03T00:00:00+00:00",
+                ),
+            ],
+        );
+        let merged = |dedup| -> Vec<(String, Vec<String>)> {
+            merge_feeds(
+                "Games".into(),
+                "https://games.com/rss".try_into().unwrap(),
+                vec![channel.clone(), clips.clone()],
+                &MergeOptions {
+                    dedup,
+                    ..Default::default()
+                },
+            )
+            .entries
+            .into_iter()
+            .map(|entry| (entry.id, entry.sources))
+            .collect()
+        };
+
+        assert_eq!(merged(DedupStrategy::Off).len(), 3);
+        assert_eq!(
+            merged(DedupStrategy::Link),
+            vec![
+                ("clips:2".into(), vec!["https://clips.com/rss".into()]),
+                (
+                    "clips:1".into(),
+                    vec![
+                        "https://clips.com/rss".into(),
+                        "https://channel.com/rss".into()
+                    ]
+                ),
+            ]
+        );
+        assert_eq!(
+            merged(DedupStrategy::Title),
+            vec![(
+                "clips:1".into(),
+                vec![
+                    "https://clips.com/rss".into(),
+                    "https://channel.com/rss".into()
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn ids_that_are_only_unique_within_a_feed_are_not_duplicates() {
+        let feed = |link: &str| Feed {
+            author_name: UNKNOWN_AUTHOR.into(),
+            id: link.into(),
+            link: link.try_into().unwrap(),
+            title: link.into(),
+            entries: vec![Entry {
+                id: "1".into(),
+                ..Entry::for_test(&format!("{}posts/1", link))
+            }],
+        };
+
+        let merged = merge_feeds(
+            "Friends".into(),
+            "https://friends.com/rss".try_into().unwrap(),
+            vec![feed("https://james.com/"), feed("https://jessica.com/")],
+            &MergeOptions {
+                dedup: DedupStrategy::Link,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(merged.entries.len(), 2);
+    }
+
+    #[test]
+    fn entries_are_limited_by_count_age_and_source() {
+        let now = Utc::now();
+        let days_ago = |days: i64| now - Duration::days(days);
+        let entry = |id: &str, published: DateTime<Utc>| Entry {
+            id: id.into(),
+            title: id.into(),
+            updated: published,
+            published,
+            ..Entry::for_test(&format!("https://example.com/{}", id))
+        };
+        let chatty = Feed {
+            author_name: UNKNOWN_AUTHOR.into(),
+            id: "Chatty".into(),
+            link: "https://chatty.com/rss".try_into().unwrap(),
+            title: "Chatty".into(),
+            entries: (0..5)
+                .map(|day| entry(&format!("chatty-{}", day), days_ago(day)))
+                .collect(),
+        };
+        let quiet = Feed {
+            author_name: UNKNOWN_AUTHOR.into(),
+            id: "Quiet".into(),
+            link: "https://quiet.com/rss".try_into().unwrap(),
+            title: "Quiet".into(),
+            entries: vec![
+                entry("quiet-3", days_ago(3)),
+                entry("quiet-30", days_ago(30)),
+            ],
+        };
+        let merged_ids = |options: MergeOptions| -> Vec<String> {
+            merge_feeds(
+                "All".into(),
+                "https://all.com/rss".try_into().unwrap(),
+                vec![chatty.clone(), quiet.clone()],
+                &options,
+            )
+            .entries
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect()
+        };
+
+        assert_eq!(
+            merged_ids(MergeOptions {
+                max_entries_per_source: Some(2),
+                ..Default::default()
+            }),
+            vec!["chatty-0", "chatty-1", "quiet-3", "quiet-30"]
+        );
+        assert_eq!(
+            merged_ids(MergeOptions {
+                max_age: Some(Duration::days(7)),
+                max_entries: Some(5),
+                ..Default::default()
+            }),
+            vec!["chatty-0", "chatty-1", "chatty-2", "chatty-3", "quiet-3"]
+        );
+    }
 }
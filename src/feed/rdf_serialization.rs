@@ -0,0 +1,176 @@
+// The yaserde derive macros generate impl blocks inside anonymous consts.
+#![allow(non_local_definitions)]
+
+use super::dates::{parse_date, parse_date_or};
+use super::model::{Author, Entry, Feed, UNKNOWN_AUTHOR};
+use super::serialization::{
+    entry_id, feed_link, images_in_html, invalid_xml_structure, partition_entries,
+    FeedDeserializer, ParsedFeed,
+};
+use anyhow::Result;
+use chrono::Utc;
+use url::Url;
+use yaserde::de::from_reader;
+use yaserde_derive::YaDeserialize;
+
+/// Deserializer for rss 1.0, where items are siblings of the channel instead of children.
+pub struct RdfDeserializer {}
+
+#[derive(YaDeserialize, Default, Debug, PartialEq)]
+#[yaserde(
+    prefix = "rdf",
+    root = "RDF",
+    namespace = "rdf: http://www.w3.org/1999/02/22-rdf-syntax-ns#",
+    namespace = "dc: http://purl.org/dc/elements/1.1/",
+    namespace = "http://purl.org/rss/1.0/"
+)]
+struct Rdf {
+    channel: Channel,
+    #[yaserde(rename = "item")]
+    items: Vec<Item>,
+}
+
+#[derive(YaDeserialize, Default, Debug, PartialEq)]
+#[yaserde(
+    namespace = "dc: http://purl.org/dc/elements/1.1/",
+    namespace = "http://purl.org/rss/1.0/"
+)]
+struct Channel {
+    title: String,
+    link: String,
+    #[yaserde(prefix = "dc", rename = "date")]
+    date: String,
+}
+
+#[derive(YaDeserialize, Default, Debug, PartialEq)]
+#[yaserde(
+    namespace = "dc: http://purl.org/dc/elements/1.1/",
+    namespace = "http://purl.org/rss/1.0/"
+)]
+struct Item {
+    #[yaserde(attribute, prefix = "rdf", rename = "about")]
+    about: String,
+    title: String,
+    link: String,
+    description: String,
+    #[yaserde(prefix = "dc", rename = "date")]
+    date: String,
+    #[yaserde(prefix = "dc", rename = "creator")]
+    creator: Option<String>,
+}
+
+impl FeedDeserializer for RdfDeserializer {
+    fn parse_feed_with_warnings(&self, bytes: &[u8], source_url: &Url) -> Result<ParsedFeed> {
+        let rdf: Rdf = from_reader(bytes).map_err(invalid_xml_structure)?;
+
+        let link = feed_link(Some(&rdf.channel.link), source_url)?;
+
+        // Entries without a usable date are treated as new as the feed itself.
+        let feed_date = parse_date(&rdf.channel.date).unwrap_or_else(Utc::now);
+
+        let entry_results: Vec<Result<Entry>> = rdf
+            .items
+            .into_iter()
+            .map(|it| {
+                if it.link.is_empty() {
+                    return Err(invalid_xml_structure(
+                        "Missing 'link' element in rdf item".into(),
+                    ));
+                }
+                let media = images_in_html(&it.description)?;
+                let published = parse_date_or(&it.date, feed_date);
+                Ok(Entry {
+                    id: entry_id(&it.about, &it.link, &it.title),
+                    link: it.link,
+                    summary: it.description,
+                    title: it.title,
+                    author: it.creator.map(|name| Author { name, uri: None }),
+                    sources: vec![],
+                    media,
+                    updated: published,
+                    published,
+                })
+            })
+            .collect();
+        let (entries, warnings) = partition_entries(entry_results);
+
+        Ok(ParsedFeed {
+            feed: Feed {
+                author_name: UNKNOWN_AUTHOR.into(),
+                id: rdf.channel.title.clone(),
+                entries,
+                link,
+                title: rdf.channel.title,
+            },
+            warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod parser_tests {
+    use super::super::model::{Media, MediaKind};
+    use super::super::serialization::example_source_url;
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn rdf_feed_can_be_parsed() {
+        let feed_str = std::fs::read_to_string("src/res/example_rdf_feed.xml")
+            .expect("Expected example file to exist.");
+        let parser = RdfDeserializer {};
+
+        let feed = parser
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
+
+        let expected = Feed {
+            author_name: "Unknown".into(),
+            entries: vec![
+                Entry {
+                    title: "Still blogging".into(),
+                    id: "https://oldblog.example.com/posts/2".into(),
+                    link: "https://oldblog.example.com/posts/2".into(),
+                    summary:
+                        r#"<p>Yes, really.</p><img src="https://oldblog.example.com/cat.jpg"/>"#
+                            .into(),
+                    author: Some(Author {
+                        name: "Webmaster".into(),
+                        uri: None,
+                    }),
+                    sources: vec![],
+                    media: vec![Media {
+                        kind: MediaKind::Thumbnail,
+                        url: "https://oldblog.example.com/cat.jpg".into(),
+                        media_type: None,
+                    }],
+                    updated: DateTime::parse_from_rfc3339("2022-04-08T23:23:14+00:00")
+                        .unwrap()
+                        .into(),
+                    published: DateTime::parse_from_rfc3339("2022-04-08T23:23:14+00:00")
+                        .unwrap()
+                        .into(),
+                },
+                Entry {
+                    title: "First post".into(),
+                    id: "https://oldblog.example.com/posts/1".into(),
+                    link: "https://oldblog.example.com/posts/1".into(),
+                    summary: "Hello world".into(),
+                    author: None,
+                    sources: vec![],
+                    media: vec![],
+                    updated: DateTime::parse_from_rfc3339("2022-04-10T12:00:00+00:00")
+                        .unwrap()
+                        .into(),
+                    published: DateTime::parse_from_rfc3339("2022-04-10T12:00:00+00:00")
+                        .unwrap()
+                        .into(),
+                },
+            ],
+            id: "Old Blog".into(),
+            link: "https://oldblog.example.com/".try_into().unwrap(),
+            title: "Old Blog".into(),
+        };
+        assert_eq!(expected, feed);
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::{Context, Error, Result};
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::convert::TryFrom;
+use url::Url;
+
+use super::model::Entry;
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RewriteField {
+    Id,
+    Link,
+    Summary,
+}
+
+// Ids are left alone unless a rule asks for them, so readers don't see entries again when a frontend changes.
+const DEFAULT_FIELDS: [RewriteField; 2] = [RewriteField::Link, RewriteField::Summary];
+
+/// Rewrites urls in entries, e.g. to send links to a frontend instead of the site itself.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "RewriteRuleFormat")]
+pub struct RewriteRule {
+    regex: Regex,
+    replacement: String,
+    fields: Vec<RewriteField>,
+}
+
+impl PartialEq for RewriteRule {
+    fn eq(&self, other: &RewriteRule) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+            && self.replacement == other.replacement
+            && self.fields == other.fields
+    }
+}
+
+// A rule is written as a table with either host and to, or regex and replace.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RewriteRuleFormat {
+    host: Option<String>,
+    to: Option<String>,
+    regex: Option<String>,
+    replace: Option<String>,
+    #[serde(default)]
+    fields: Vec<RewriteField>,
+}
+
+impl TryFrom<RewriteRuleFormat> for RewriteRule {
+    type Error = Error;
+
+    fn try_from(format: RewriteRuleFormat) -> Result<RewriteRule> {
+        let rule = match (format.host, format.to, format.regex, format.replace) {
+            (Some(host), Some(to), None, None) => {
+                let to = Url::parse(&to).with_context(|| format!("Invalid rewrite url {}", to))?;
+                RewriteRule::host(&host, &to)?
+            }
+            (None, None, Some(regex), Some(replacement)) => RewriteRule {
+                regex: Regex::new(&regex)
+                    .with_context(|| format!("Invalid rewrite regex {}", regex))?,
+                replacement,
+                fields: DEFAULT_FIELDS.to_vec(),
+            },
+            _ => {
+                return Err(Error::msg(
+                    "A rewrite needs either host and to, or regex and replace",
+                ))
+            }
+        };
+        Ok(if format.fields.is_empty() {
+            rule
+        } else {
+            RewriteRule {
+                fields: format.fields,
+                ..rule
+            }
+        })
+    }
+}
+
+impl RewriteRule {
+    /// Move urls on the host under another url, keeping their path and query,
+    /// so https://example.com/invidious/ turns youtube.com/watch into example.com/invidious/watch.
+    pub fn host(host: &str, to: &Url) -> Result<RewriteRule> {
+        if !to.origin().is_tuple() {
+            return Err(Error::msg(format!("Rewrite url {} has no host", to)));
+        }
+        let mut prefix = to.clone();
+        prefix.set_query(None);
+        prefix.set_fragment(None);
+        // The matched url brings its own slash, and $ would be taken for a group in the replacement.
+        let prefix = prefix.as_str().trim_end_matches('/').replace('$', "$$");
+        // The host has to be followed by something that can't be part of it,
+        // so www.reddit.com doesn't match www.reddit.com.example.com.
+        let regex = format!(
+            r#"https?://{}(?::\d+)?([/?#"'<>\s]|$)"#,
+            regex::escape(host)
+        );
+        Ok(RewriteRule {
+            regex: Regex::new(&regex).with_context(|| format!("Invalid rewrite host {}", host))?,
+            replacement: format!("{}${{1}}", prefix),
+            fields: DEFAULT_FIELDS.to_vec(),
+        })
+    }
+
+    pub fn apply(&self, entry: &mut Entry) {
+        for field in self.fields.iter() {
+            let value = match field {
+                RewriteField::Id => &mut entry.id,
+                RewriteField::Link => &mut entry.link,
+                RewriteField::Summary => &mut entry.summary,
+            };
+            *value = self
+                .regex
+                .replace_all(value, self.replacement.as_str())
+                .into_owned();
+        }
+    }
+}
+
+pub fn rewrite(entry: &mut Entry, rules: &[RewriteRule]) {
+    for rule in rules {
+        rule.apply(entry);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(link: &str, summary: &str) -> Entry {
+        Entry {
+            summary: summary.into(),
+            ..Entry::for_test(link)
+        }
+    }
+
+    fn rules(toml: &str) -> Result<Vec<RewriteRule>, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct Rules {
+            rewrites: Vec<RewriteRule>,
+        }
+        toml::from_str::<Rules>(toml).map(|rules| rules.rewrites)
+    }
+
+    #[test]
+    fn hosts_are_mapped_in_links_and_summaries() {
+        let rules =
+            rules(r#"rewrites = [{ host = "twitter.com", to = "https://nitter.example.com" }]"#)
+                .unwrap();
+        let mut entry = entry(
+            "https://twitter.com/user/status/1",
+            r#"<a href="http://twitter.com/other">@other</a> https://twitter.com"#,
+        );
+
+        rewrite(&mut entry, &rules);
+
+        assert_eq!(entry.link, "https://nitter.example.com/user/status/1");
+        assert_eq!(entry.id, "https://twitter.com/user/status/1");
+        assert_eq!(
+            entry.summary,
+            r#"<a href="https://nitter.example.com/other">@other</a> https://nitter.example.com"#
+        );
+    }
+
+    #[test]
+    fn ids_are_only_mapped_when_listed() {
+        let rules = rules(
+            r#"rewrites = [{ host = "twitter.com", to = "https://nitter.example.com", fields = ["id"] }]"#,
+        )
+        .unwrap();
+        let mut entry = entry("https://twitter.com/user/status/1", "");
+
+        rewrite(&mut entry, &rules);
+
+        assert_eq!(entry.id, "https://nitter.example.com/user/status/1");
+        assert_eq!(entry.link, "https://twitter.com/user/status/1");
+    }
+
+    #[test]
+    fn hosts_can_be_mapped_under_a_path() {
+        let rules = rules(
+            r#"rewrites = [{ host = "youtube.com", to = "https://example.com/invidious/" }]"#,
+        )
+        .unwrap();
+        let mut entry = entry(
+            "https://youtube.com/watch?v=1",
+            "https://youtube.com https://youtube.com?v=2",
+        );
+
+        rewrite(&mut entry, &rules);
+
+        assert_eq!(entry.link, "https://example.com/invidious/watch?v=1");
+        assert_eq!(
+            entry.summary,
+            "https://example.com/invidious https://example.com/invidious?v=2"
+        );
+    }
+
+    #[test]
+    fn other_hosts_are_left_alone() {
+        let rules =
+            rules(r#"rewrites = [{ host = "youtube.com", to = "https://yt.example.com" }]"#)
+                .unwrap();
+        let mut entry = entry(
+            "https://youtube.com.example.org/watch?v=1",
+            "https://m.youtube.com/watch?v=1",
+        );
+
+        rewrite(&mut entry, &rules);
+
+        assert_eq!(entry.link, "https://youtube.com.example.org/watch?v=1");
+        assert_eq!(entry.summary, "https://m.youtube.com/watch?v=1");
+    }
+
+    #[test]
+    fn regexes_only_replace_in_their_fields() {
+        let rules = rules(
+            r#"rewrites = [{ regex = "https://medium\\.com/@(\\w+)/", replace = "https://scribe.example.com/@$1/", fields = ["link"] }]"#,
+        )
+        .unwrap();
+        let mut entry = entry("https://medium.com/@writer/post-1", "");
+
+        rewrite(&mut entry, &rules);
+
+        assert_eq!(entry.link, "https://scribe.example.com/@writer/post-1");
+        assert_eq!(entry.id, "https://medium.com/@writer/post-1");
+    }
+
+    #[test]
+    fn rules_need_a_host_or_a_regex() {
+        assert!(rules(r#"rewrites = [{ host = "twitter.com" }]"#).is_err());
+        assert!(rules(r#"rewrites = [{ host = "a.com", to = "b.com" }]"#).is_err());
+        assert!(
+            rules(r#"rewrites = [{ host = "a.com", to = "https://b.com", regex = "a" }]"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,98 @@
+// The yaserde derive macros generate impl blocks inside anonymous consts.
+#![allow(non_local_definitions)]
+
+use derive_alias::derive_alias;
+use yaserde_derive::YaSerialize;
+
+use super::atom::MediaThumbnail;
+
+derive_alias! {
+    derive_item => #[derive(YaSerialize, Default, Debug, PartialEq)]
+}
+
+derive_item! {
+    #[yaserde(
+        namespace = "atom: http://www.w3.org/2005/Atom"
+        namespace = "dc: http://purl.org/dc/elements/1.1/"
+        namespace = "media: http://search.yahoo.com/mrss/"
+        root = "rss"
+        rename = "rss"
+    )]
+    pub struct RssFeed {
+        #[yaserde(attribute)]
+        pub version: String,
+        pub channel: RssChannel,
+    }
+}
+
+derive_item! {
+    #[yaserde(
+        namespace = "atom: http://www.w3.org/2005/Atom"
+    )]
+    pub struct RssChannel {
+        #[yaserde(rename="link", prefix="atom")]
+        pub self_link: RssAtomLink,
+        pub title: String,
+        pub link: String,
+        pub description: String,
+        #[yaserde(rename="lastBuildDate")]
+        pub last_build_date: String,
+        #[yaserde(rename="item")]
+        pub items: Vec<RssItem>,
+    }
+}
+
+derive_item! {
+    #[yaserde(
+        prefix = "atom",
+        namespace = "atom: http://www.w3.org/2005/Atom"
+    )]
+    pub struct RssAtomLink {
+        #[yaserde(attribute)]
+        pub href: String,
+        #[yaserde(attribute)]
+        pub rel: String,
+        #[yaserde(attribute, rename="type")]
+        pub link_type: String,
+    }
+}
+
+// Declared outside of derive_item!, since yaserde can't expand optional text fields inside of it.
+#[derive(YaSerialize, Default, Debug, PartialEq)]
+#[yaserde(
+    namespace = "dc: http://purl.org/dc/elements/1.1/",
+    namespace = "media: http://search.yahoo.com/mrss/"
+)]
+pub struct RssItem {
+    pub title: String,
+    pub link: String,
+    pub guid: RssGuid,
+    #[yaserde(rename = "pubDate")]
+    pub pub_date: String,
+    pub description: String,
+    #[yaserde(prefix = "dc", rename = "creator")]
+    pub creator: Option<String>,
+    pub enclosure: Option<RssEnclosure>,
+    #[yaserde(prefix = "media", rename = "thumbnail")]
+    pub media_thumbnails: Vec<MediaThumbnail>,
+}
+
+derive_item! {
+    pub struct RssEnclosure {
+        #[yaserde(attribute)]
+        pub url: String,
+        #[yaserde(attribute)]
+        pub length: String,
+        #[yaserde(attribute, rename="type")]
+        pub enclosure_type: String,
+    }
+}
+
+derive_item! {
+    pub struct RssGuid {
+        #[yaserde(attribute, rename="isPermaLink")]
+        pub is_perma_link: String,
+        #[yaserde(text)]
+        pub value: String,
+    }
+}
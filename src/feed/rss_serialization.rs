@@ -1,8 +1,15 @@
-use super::model::Entry;
+// The yaserde derive macros generate impl blocks inside anonymous consts.
+#![allow(non_local_definitions)]
+
+use super::dates::{parse_date, parse_date_or};
 use super::model::Feed;
-use super::serialization::{invalid_xml_structure, FeedDeserializer};
-use anyhow::{Context, Result};
-use chrono::DateTime;
+use super::model::{Author, Entry, Media, MediaKind, UNKNOWN_AUTHOR};
+use super::serialization::{
+    entry_id, feed_link, images_in_html, invalid_xml_structure, partition_entries,
+    FeedDeserializer, ParsedFeed,
+};
+use anyhow::Result;
+use chrono::Utc;
 use url::Url;
 use yaserde::de::from_reader;
 use yaserde_derive::YaDeserialize;
@@ -21,11 +28,16 @@ struct Channel {
     #[yaserde(prefix = "atom", rename = "link")]
     link: Vec<Link>,
     title: String,
+    #[yaserde(rename = "lastBuildDate")]
+    last_build_date: String,
+    #[yaserde(rename = "pubDate")]
+    published: String,
     #[yaserde(rename = "item")]
     items: Vec<Item>,
 }
 
 #[derive(YaDeserialize, Default, Debug, PartialEq)]
+#[yaserde(namespace = "dc: http://purl.org/dc/elements/1.1/")]
 struct Item {
     #[yaserde(rename = "guid")]
     id: String,
@@ -33,7 +45,19 @@ struct Item {
     description: String,
     title: String,
     #[yaserde(rename = "pubDate")]
-    updated: String,
+    published: String,
+    #[yaserde(prefix = "dc", rename = "creator")]
+    creator: Option<String>,
+    #[yaserde(rename = "enclosure")]
+    enclosures: Vec<Enclosure>,
+}
+
+#[derive(YaDeserialize, Default, Debug, PartialEq)]
+struct Enclosure {
+    #[yaserde(attribute)]
+    url: String,
+    #[yaserde(attribute, rename = "type")]
+    enclosure_type: String,
 }
 
 #[derive(YaDeserialize, Default, Debug, PartialEq)]
@@ -53,7 +77,7 @@ struct Link {
 }
 
 impl FeedDeserializer for RssDeserializer {
-    fn parse_feed_from_bytes(&self, bytes: &[u8]) -> Result<Feed> {
+    fn parse_feed_with_warnings(&self, bytes: &[u8], source_url: &Url) -> Result<ParsedFeed> {
         let mut rss: Rss = from_reader(bytes).map_err(invalid_xml_structure)?;
 
         // Even though we require that the link element should have the atom namespace, the regular rss link element is still included.
@@ -65,49 +89,71 @@ impl FeedDeserializer for RssDeserializer {
             .find(|li| li.link_type == "application/rss+xml")
             .map(|link| &link.href)
             // twitchrss doesn't have an atom link, so we rely on a plain <link>{url_goes_here}</link> element.
-            .or_else(|| rss.channel.link.get(0).map(|li| &li.content))
-            .ok_or(anyhow::Error::msg("No links found"))?;
+            .or_else(|| rss.channel.link.first().map(|li| &li.content));
+        let link = feed_link(href.map(String::as_str), source_url)?;
 
-        let link = Url::parse(href)
-            .map_err(|err| invalid_xml_structure(format!("Invalid feed url {}", err)))?;
+        // Entries without a usable date are treated as new as the feed itself.
+        let feed_date = parse_date(&rss.channel.last_build_date)
+            .or_else(|| parse_date(&rss.channel.published))
+            .unwrap_or_else(Utc::now);
 
-        let items = std::mem::replace(&mut rss.channel.items, vec![]);
+        let items = std::mem::take(&mut rss.channel.items);
         let entry_results: Vec<Result<Entry>> = items
             .into_iter()
             .map(|it| {
+                if it.link.is_empty() {
+                    return Err(invalid_xml_structure(
+                        "Missing 'link' element in rss item".into(),
+                    ));
+                }
+                let media = item_media(&it)?;
+                // rss has no notion of updates, so an entry is only ever updated when it's published.
+                let published = parse_date_or(&it.published, feed_date);
                 Ok(Entry {
-                    id: it.link.clone(),
+                    id: entry_id(&it.id, &it.link, &it.title),
                     link: it.link,
                     summary: it.description,
                     title: it.title,
-                    updated: DateTime::parse_from_rfc2822(&it.updated)
-                        .map_err(|_dt_err| {
-                            invalid_xml_structure(format!(
-                                "Invalid rss entry date time: {}",
-                                _dt_err
-                            ))
-                        })?
-                        .into(),
+                    author: it.creator.map(|name| Author { name, uri: None }),
+                    sources: vec![],
+                    media,
+                    updated: published,
+                    published,
                 })
             })
             .collect();
-        let mut entries = vec![];
-        for e in entry_results {
-            entries.push(e.context("Failed to deserialize an atom feed entry")?);
-        }
+        let (entries, warnings) = partition_entries(entry_results);
 
-        Ok(Feed {
-            author_name: "Unknown".into(),
-            id: rss.channel.title.clone(),
-            entries: entries,
-            link: link,
-            title: rss.channel.title,
+        Ok(ParsedFeed {
+            feed: Feed {
+                author_name: UNKNOWN_AUTHOR.into(),
+                id: rss.channel.title.clone(),
+                entries,
+                link,
+                title: rss.channel.title,
+            },
+            warnings,
         })
     }
 }
 
+fn item_media(it: &Item) -> Result<Vec<Media>> {
+    let enclosures = it.enclosures.iter().map(|enclosure| Media {
+        kind: MediaKind::Enclosure,
+        url: enclosure.url.clone(),
+        media_type: Some(enclosure.enclosure_type.clone()).filter(|t| !t.is_empty()),
+    });
+
+    // nitter only includes images in the html description.
+    let images = images_in_html(&it.description)?;
+
+    Ok(enclosures.chain(images).collect())
+}
+
 #[cfg(test)]
 mod parser_tests {
+    use super::super::serialization::example_source_url;
+    use super::super::serialization::EntryWarning;
     use super::*;
     use chrono::DateTime;
 
@@ -117,7 +163,9 @@ mod parser_tests {
             .expect("Expected example file to exist.");
         let parser = RssDeserializer {};
 
-        let feed = parser.parse_feed_from_bytes(feed_str.as_bytes()).unwrap();
+        let feed = parser
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
 
         let expected = Feed {
             author_name: "Unknown".into(),
@@ -135,7 +183,9 @@ mod parser_tests {
             .expect("Expected example file to exist.");
         let parser = RssDeserializer {};
 
-        let feed = parser.parse_feed_from_bytes(feed_str.as_bytes()).unwrap();
+        let feed = parser
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
 
         let expected = Feed {
             author_name: "Unknown".into(),
@@ -144,8 +194,16 @@ mod parser_tests {
                     title: "messing around in photoshop on twitch. not sure for how long. maybe 15 minutes. maybe 24 hours. probably not 24 hours though http://twitch.tv/harddrivenews".into(),
                     id: "https://nitter.net/HardDriveMag/status/1512602002425004039#m".into(),
                     link: "https://nitter.net/HardDriveMag/status/1512602002425004039#m".into(),
+                    author: Some(Author { name: "@HardDriveMag".into(), uri: None }),
+                    sources: vec![],
+                    media: vec![Media {
+                        kind: MediaKind::Thumbnail,
+                        url: "https://nitter.net/pic/media%2FFP3Wqt-XMAQ7IIK.png".into(),
+                        media_type: None,
+                    }],
                     summary: r##"<p>messing around in photoshop on twitch. not sure for how long. maybe 15 minutes. maybe 24 hours. probably not 24 hours though <a href="http://twitch.tv/harddrivenews">twitch.tv/harddrivenews</a></p><img src="https://nitter.net/pic/media%2FFP3Wqt-XMAQ7IIK.png" style="max-width:250px;" />"##.into(),
-                    updated: DateTime::parse_from_rfc3339("2022-04-09T01:23:14+00:00").unwrap().into()
+                    updated: DateTime::parse_from_rfc3339("2022-04-09T01:23:14+00:00").unwrap().into(),
+                    published: DateTime::parse_from_rfc3339("2022-04-09T01:23:14+00:00").unwrap().into()
                 }
             ],
             id: "Hard Drive / @HardDriveMag".into(),
@@ -162,18 +220,26 @@ mod parser_tests {
             .expect("Expected example file to exist.");
         let parser = RssDeserializer {};
 
-        let feed = parser.parse_feed_from_bytes(feed_str.as_bytes()).unwrap();
+        let feed = parser
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
 
         let expected = Feed {
             author_name: "Unknown".into(),
             entries: vec![Entry {
                 title: "Last Stream for a week! Rogue Legacy 2!".into(),
-                id: "https://www.twitch.tv/videos/1473376952".into(),
+                id: "1473376952".into(),
                 link: "https://www.twitch.tv/videos/1473376952".into(),
                 summary: r##"< shorter >"##.into(),
+                author: None,
+                sources: vec![],
+                media: vec![],
                 updated: DateTime::parse_from_rfc3339("2022-05-02T21:31:55+00:00")
                     .unwrap()
                     .into(),
+                published: DateTime::parse_from_rfc3339("2022-05-02T21:31:55+00:00")
+                    .unwrap()
+                    .into(),
             }],
             id: "TieTuesday's Twitch video RSS".into(),
             link: "https://twitchrss.appspot.com/".try_into().unwrap(),
@@ -182,4 +248,93 @@ mod parser_tests {
 
         assert_eq!(expected, feed);
     }
+
+    #[test]
+    fn feed_without_links_falls_back_to_the_source_url() {
+        let feed_str = r#"<rss version="2.0"><channel><title>No links</title></channel></rss>"#;
+        let parser = RssDeserializer {};
+
+        let feed = parser
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
+
+        assert_eq!(example_source_url(), feed.link);
+    }
+
+    #[test]
+    fn items_without_guid_get_a_stable_tag_uri() {
+        let feed_str = std::fs::read_to_string("src/res/example_invalid_entry_rss_feed.xml")
+            .expect("Expected example file to exist.");
+        let parser = RssDeserializer {};
+
+        let first = parser
+            .parse_feed_with_warnings(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
+        let second = parser
+            .parse_feed_with_warnings(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
+
+        let id = &first.feed.entries[0].id;
+        assert!(id.starts_with("tag:feedragon,2022:"), "{}", id);
+        assert_eq!(id, &second.feed.entries[0].id);
+    }
+
+    #[test]
+    fn malformed_and_missing_dates_do_not_fail_the_feed() {
+        let feed_str = std::fs::read_to_string("src/res/example_malformed_dates_rss_feed.xml")
+            .expect("Expected example file to exist.");
+        let parser = RssDeserializer {};
+
+        let feed = parser
+            .parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
+
+        let expected: Vec<DateTime<Utc>> = vec![
+            DateTime::parse_from_rfc3339("2022-04-08T23:23:14+00:00")
+                .unwrap()
+                .into(),
+            DateTime::parse_from_rfc3339("2022-04-10T12:00:00+00:00")
+                .unwrap()
+                .into(),
+        ];
+        let published: Vec<DateTime<Utc>> = feed.entries.iter().map(|e| e.published).collect();
+        assert_eq!(expected, published);
+    }
+
+    #[test]
+    fn invalid_entries_are_skipped_with_a_warning() {
+        let feed_str = std::fs::read_to_string("src/res/example_invalid_entry_rss_feed.xml")
+            .expect("Expected example file to exist.");
+        let parser = RssDeserializer {};
+
+        let parsed = parser
+            .parse_feed_with_warnings(feed_str.as_bytes(), &example_source_url())
+            .unwrap();
+
+        let links: Vec<&str> = parsed
+            .feed
+            .entries
+            .iter()
+            .map(|e| e.link.as_str())
+            .collect();
+        assert_eq!(vec!["https://blog.example.com/posts/1"], links);
+        assert_eq!(
+            vec![EntryWarning {
+                index: 0,
+                reason: "Invalid xml structure: Missing 'link' element in rss item".into()
+            }],
+            parsed.warnings
+        );
+    }
+
+    #[test]
+    fn invalid_entries_fail_strict_parsing() {
+        let feed_str = std::fs::read_to_string("src/res/example_invalid_entry_rss_feed.xml")
+            .expect("Expected example file to exist.");
+        let parser = RssDeserializer {};
+
+        let result = parser.parse_feed_from_bytes(feed_str.as_bytes(), &example_source_url());
+
+        assert!(result.is_err());
+    }
 }
@@ -1,15 +1,128 @@
 use anyhow::Result;
-use anyhow::*;
+use anyhow::{Context, Error};
+use derive_more::Display;
+use md5::{Digest, Md5};
+use scraper::{Html, Selector};
+use url::Url;
 
-use super::atom::{AtomEntry, AtomEntryLink, AtomFeed, AtomLink};
+use super::atom::{AtomAuthor, AtomEntry, AtomFeed, AtomLink, AtomText, MediaThumbnail};
+use super::json::{JsonFeed, JsonFeedAttachment, JsonFeedAuthor, JsonFeedItem, JSON_FEED_VERSION};
+use super::model::{Entry, Media, MediaKind, UNKNOWN_AUTHOR};
+use super::rss::{RssAtomLink, RssChannel, RssEnclosure, RssFeed, RssGuid, RssItem};
 use super::Feed;
 
 pub fn invalid_xml_structure(s: String) -> Error {
     Error::msg(format!("Invalid xml structure: {}", s))
 }
 
+#[derive(Display, Debug, PartialEq, Clone)]
+#[display(fmt = "Skipped entry {}: {}", index, reason)]
+pub struct EntryWarning {
+    pub index: usize,
+    pub reason: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedFeed {
+    pub feed: Feed,
+    pub warnings: Vec<EntryWarning>,
+}
+
+impl ParsedFeed {
+    /// Turn the first warning into an error, if there is one.
+    pub fn into_strict(self) -> Result<Feed> {
+        match self.warnings.into_iter().next() {
+            Some(warning) => Err(Error::msg(warning.reason))
+                .with_context(|| format!("Failed to deserialize feed entry {}", warning.index)),
+            None => Ok(self.feed),
+        }
+    }
+}
+
 pub trait FeedDeserializer: Send + Sync {
-    fn parse_feed_from_bytes(&self, bytes: &[u8]) -> Result<Feed>;
+    /// Parse a feed, skipping entries that are invalid and reporting them as warnings instead.
+    /// The source url is the url the feed was subscribed to, which is used as the feed link
+    /// when the feed doesn't link to itself.
+    fn parse_feed_with_warnings(&self, bytes: &[u8], source_url: &Url) -> Result<ParsedFeed>;
+
+    /// Like parse_feed_with_warnings, but with the content type of the http response as a hint.
+    fn parse_feed_with_content_type(
+        &self,
+        bytes: &[u8],
+        source_url: &Url,
+        _content_type: Option<&str>,
+    ) -> Result<ParsedFeed> {
+        self.parse_feed_with_warnings(bytes, source_url)
+    }
+
+    /// Parse a feed, failing on the first invalid entry.
+    #[cfg(test)]
+    fn parse_feed_from_bytes(&self, bytes: &[u8], source_url: &Url) -> Result<Feed> {
+        self.parse_feed_with_warnings(bytes, source_url)?
+            .into_strict()
+    }
+}
+
+/// The url that test feeds are pretended to be subscribed to.
+#[cfg(test)]
+pub fn example_source_url() -> Url {
+    Url::parse("https://feeds.example.com/subscribed.xml").unwrap()
+}
+
+/// Parse the self link of a feed, or fall back to the url the feed was subscribed to.
+pub fn feed_link(href: Option<&str>, source_url: &Url) -> Result<Url> {
+    match href.filter(|href| !href.trim().is_empty()) {
+        Some(href) => Url::parse(href.trim())
+            .map_err(|err| invalid_xml_structure(format!("Invalid feed url {}", err))),
+        None => Ok(source_url.clone()),
+    }
+}
+
+/// Find the images of an html description, which is where some feeds put their thumbnails.
+pub fn images_in_html(html: &str) -> Result<Vec<Media>> {
+    let selector = Selector::parse("img[src]")
+        .map_err(|e| Error::msg(format!("Could not parse selector {e:?}")))?;
+    let images = Html::parse_fragment(html)
+        .select(&selector)
+        .flat_map(|element_ref| element_ref.value().attr("src"))
+        .map(|src| Media {
+            kind: MediaKind::Thumbnail,
+            url: src.into(),
+            media_type: None,
+        })
+        .collect();
+    Ok(images)
+}
+
+/// The identity of an entry, which is its guid or id when the feed has one.
+/// Otherwise a tag uri is derived from the link and title, so that it stays the same between fetches.
+pub fn entry_id(id: &str, link: &str, title: &str) -> String {
+    let id = id.trim();
+    if !id.is_empty() {
+        return id.into();
+    }
+    let digest = Md5::new()
+        .chain_update(link.trim())
+        .chain_update("\n")
+        .chain_update(title.trim())
+        .finalize();
+    format!("tag:feedragon,2022:{}", hex::encode(digest))
+}
+
+/// Separate the valid entries from the invalid ones, keeping track of where the invalid ones were.
+pub fn partition_entries(entry_results: Vec<Result<Entry>>) -> (Vec<Entry>, Vec<EntryWarning>) {
+    let mut entries = vec![];
+    let mut warnings = vec![];
+    for (index, result) in entry_results.into_iter().enumerate() {
+        match result {
+            Ok(entry) => entries.push(entry),
+            Err(err) => warnings.push(EntryWarning {
+                index,
+                reason: format!("{:#}", err),
+            }),
+        }
+    }
+    (entries, warnings)
 }
 
 impl Feed {
@@ -21,31 +134,183 @@ impl Feed {
                 rel: "self".into(),
                 href: self.link.as_str().into(),
             }],
+            author: Some(self.author_name)
+                .filter(|name| name != UNKNOWN_AUTHOR)
+                .map(|name| AtomAuthor { name, uri: None }),
             entries: self
                 .entries
                 .into_iter()
                 .map(|e| AtomEntry {
                     id: e.id,
-                    link: AtomEntryLink {
+                    links: std::iter::once(AtomLink {
+                        link_type: "text/html".into(),
                         rel: "alternate".into(),
                         href: e.link,
-                    },
+                    })
+                    .chain(enclosures(&e.media).map(|media| AtomLink {
+                        link_type: enclosure_type(media),
+                        rel: "enclosure".into(),
+                        href: media.url.clone(),
+                    }))
+                    // Duplicates from several feeds are merged into one entry, which links to every feed it was in.
+                    .chain(
+                        e.sources
+                            .iter()
+                            .filter(|_| e.sources.len() > 1)
+                            .map(|source| AtomLink {
+                                link_type: "application/xml".into(),
+                                rel: "via".into(),
+                                href: source.clone(),
+                            }),
+                    )
+                    .collect(),
                     title: e.title,
+                    published: e.published.format("%+").to_string(),
                     updated: e.updated.format("%+").to_string(),
+                    summary: Some(AtomText {
+                        content_type: "text".into(),
+                        value: html_to_text(&e.summary),
+                    }),
+                    content: Some(AtomText {
+                        content_type: "html".into(),
+                        value: e.summary,
+                    }),
+                    author: e.author.map(|author| AtomAuthor {
+                        name: author.name,
+                        uri: author.uri,
+                    }),
+                    media_group: None,
+                    media_thumbnails: media_thumbnails(&e.media),
+                    media_contents: vec![],
                 })
                 .collect(),
             updated: chrono::Utc::now().format("%+").to_string(),
         };
 
-        let yaserde_cfg = yaserde::ser::Config {
-            perform_indent: true,
-            ..Default::default()
+        to_xml_string(&feed)
+            .with_context(|| format!("Failed to serialize feed to string: {}", feed.title))
+    }
+
+    pub fn serialize_to_rss_string(self) -> Result<String> {
+        let feed = RssFeed {
+            version: "2.0".into(),
+            channel: RssChannel {
+                self_link: RssAtomLink {
+                    link_type: "application/rss+xml".into(),
+                    rel: "self".into(),
+                    href: self.link.as_str().into(),
+                },
+                description: self.title.clone(),
+                title: self.title,
+                link: self.link.as_str().into(),
+                last_build_date: chrono::Utc::now().to_rfc2822(),
+                items: self
+                    .entries
+                    .into_iter()
+                    .map(|e| RssItem {
+                        guid: RssGuid {
+                            is_perma_link: (e.id == e.link).to_string(),
+                            value: e.id,
+                        },
+                        link: e.link,
+                        title: e.title,
+                        pub_date: e.published.to_rfc2822(),
+                        description: e.summary,
+                        creator: e.author.map(|author| author.name),
+                        // rss only allows a single enclosure per item.
+                        enclosure: enclosures(&e.media).next().map(|media| RssEnclosure {
+                            url: media.url.clone(),
+                            length: "0".into(),
+                            enclosure_type: enclosure_type(media),
+                        }),
+                        media_thumbnails: media_thumbnails(&e.media),
+                    })
+                    .collect(),
+            },
+        };
+
+        to_xml_string(&feed).with_context(|| {
+            format!(
+                "Failed to serialize feed to rss string: {}",
+                feed.channel.title
+            )
+        })
+    }
+
+    pub fn serialize_to_json_string(self) -> Result<String> {
+        let feed = JsonFeed {
+            version: JSON_FEED_VERSION.into(),
+            title: self.title,
+            feed_url: self.link.as_str().into(),
+            items: self
+                .entries
+                .into_iter()
+                .map(|e| JsonFeedItem {
+                    id: e.id,
+                    url: e.link,
+                    title: e.title,
+                    content_html: e.summary,
+                    date_published: e.published.to_rfc3339(),
+                    date_modified: e.updated.to_rfc3339(),
+                    image: thumbnails(&e.media).next().map(|media| media.url.clone()),
+                    attachments: enclosures(&e.media)
+                        .map(|media| JsonFeedAttachment {
+                            url: media.url.clone(),
+                            mime_type: enclosure_type(media),
+                        })
+                        .collect(),
+                    authors: e
+                        .author
+                        .map(|author| JsonFeedAuthor {
+                            name: author.name,
+                            url: author.uri,
+                        })
+                        .into_iter()
+                        .collect(),
+                })
+                .collect(),
         };
 
-        Ok(yaserde::ser::to_string_with_config(&feed, &yaserde_cfg)
-            .map_err(Error::msg)
-            .with_context(|| {
-                format!("Failed to serialize feed to string: {}", feed.title.clone())
-            })?)
+        serde_json::to_string_pretty(&feed)
+            .with_context(|| format!("Failed to serialize feed to json string: {}", feed.title))
     }
 }
+
+fn enclosures(media: &[Media]) -> impl Iterator<Item = &Media> {
+    media.iter().filter(|m| m.kind == MediaKind::Enclosure)
+}
+
+fn thumbnails(media: &[Media]) -> impl Iterator<Item = &Media> {
+    media.iter().filter(|m| m.kind == MediaKind::Thumbnail)
+}
+
+fn enclosure_type(media: &Media) -> String {
+    media
+        .media_type
+        .clone()
+        .unwrap_or_else(|| "application/octet-stream".into())
+}
+
+fn media_thumbnails(media: &[Media]) -> Vec<MediaThumbnail> {
+    thumbnails(media)
+        .map(|m| MediaThumbnail { url: m.url.clone() })
+        .collect()
+}
+
+fn html_to_text(html: &str) -> String {
+    Html::parse_fragment(html)
+        .root_element()
+        .text()
+        .collect::<String>()
+        .trim()
+        .into()
+}
+
+fn to_xml_string<T: yaserde::YaSerialize>(feed: &T) -> Result<String> {
+    let yaserde_cfg = yaserde::ser::Config {
+        perform_indent: true,
+        ..Default::default()
+    };
+
+    yaserde::ser::to_string_with_config(feed, &yaserde_cfg).map_err(Error::msg)
+}
@@ -1,6 +1,13 @@
+use crate::config::CategoryConfig;
+use crate::feed::filter::{is_included, FilterRule};
+use crate::feed::model::MergeOptions;
+use crate::feed::rewrite::{rewrite, RewriteRule};
 use crate::feed::{merge_feeds, Feed, FeedDeserializer};
+use crate::feed_transformer::{FeedTransformer, Transformation};
 use crate::http_client::HttpClient;
+use crate::redact::{redact_text, redact_url_str, Redacted};
 use anyhow::{Context, Error, Result};
+use derive_more::Display;
 
 use futures::future::join_all;
 use std::collections::HashMap;
@@ -9,110 +16,224 @@ use std::vec::IntoIter;
 use tokio::task::{self, JoinHandle};
 use url::Url;
 
-// TODO: We need to support dynamic serializers, so that we don't have to keep track of which feeds are
-// atom feeds and which are rss feeds. For now, only support rss feeds.
+/// Errors that are the fault of the request or the sources, rather than feedragon itself.
+#[derive(Display, Debug, PartialEq)]
+pub enum CategoryError {
+    #[display(fmt = "Failed to find feed category {}", _0)]
+    UnknownCategory(String),
+    #[display(fmt = "Failed to fetch every source of feed category {}", _0)]
+    AllSourcesFailed(String),
+}
+
+impl std::error::Error for CategoryError {}
 
 #[derive(Clone)]
 pub struct FeedProvider {
-    categories: HashMap<String, Category>,
+    categories: Rc<HashMap<String, Category>>,
     pub http_client: Rc<dyn HttpClient>,
     feed_deserializer: Rc<dyn FeedDeserializer>,
+    rewrite_rules: Rc<Vec<RewriteRule>>,
+    keys: Rc<Vec<String>>,
 }
 
 impl FeedProvider {
     pub fn from_categories_and_http_client_and_feed_deserializer(
-        categories: HashMap<String, Vec<String>>,
+        categories: HashMap<String, CategoryConfig>,
         http_client: Rc<dyn HttpClient>,
         feed_deserializer: Rc<dyn FeedDeserializer>,
     ) -> Result<FeedProvider> {
         let categories =
             categories
                 .into_iter()
-                .map(|name_and_urls| -> Result<(String, Category)> {
-                    let feed_urls: Vec<Result<Url>> = name_and_urls
-                        .1
-                        .into_iter()
-                        .map(|url_string| {
-                            Url::parse(&url_string).with_context(|| {
-                                format!("Failed to parse url {} in category", &url_string)
+                .map(|name_and_config| -> Result<(String, Category)> {
+                    let config = &name_and_config.1;
+                    let sources: Vec<Result<Source>> = config
+                        .sources
+                        .iter()
+                        .filter(|source| source.enabled)
+                        .map(|source| {
+                            let url = Url::parse(&source.url).with_context(|| {
+                                format!(
+                                    "Failed to parse url {} in category",
+                                    redact_url_str(&source.url)
+                                )
+                            })?;
+                            let filters = config
+                                .filters
+                                .iter()
+                                .chain(source.filters.iter())
+                                .cloned()
+                                .collect();
+                            Ok(Source {
+                                url,
+                                name: source.name.clone(),
+                                filters,
+                                transformers: source.transformers.clone(),
                             })
                         })
                         .collect();
 
-                    let feed_urls: Vec<Url> = try_all(feed_urls.into_iter())
+                    let sources: Vec<Source> = try_all(sources.into_iter())
                         .with_context(|| {
-                            format!("Failed to parse url in category {}", name_and_urls.0)
+                            format!("Failed to parse url in category {}", name_and_config.0)
                         })?
                         .collect();
 
-                    Ok((name_and_urls.0, Category { feed_urls }))
+                    Ok((
+                        name_and_config.0,
+                        Category {
+                            sources,
+                            merge_options: name_and_config.1.merge_options(),
+                            skip_invalid_entries: name_and_config.1.skip_invalid_entries,
+                            keys: name_and_config.1.keys.clone(),
+                        },
+                    ))
                 });
         let categories: HashMap<_, _> = try_all(categories)
-            .with_context(|| format!("Failed to parse categories due to url conversion issues.",))?
+            .context("Failed to parse categories due to url conversion issues.")?
             .collect();
         Ok(FeedProvider {
-            categories,
+            categories: Rc::new(categories),
             http_client,
             feed_deserializer,
+            rewrite_rules: Rc::new(vec![]),
+            keys: Rc::new(vec![]),
         })
     }
 
-    pub async fn feed_by_category(&self, category_name: &str) -> Result<Feed> {
+    /// Rewrite urls in the entries of every source, before they are filtered.
+    pub fn with_rewrite_rules(mut self, rewrite_rules: Vec<RewriteRule>) -> FeedProvider {
+        self.rewrite_rules = Rc::new(rewrite_rules);
+        self
+    }
+
+    /// Require one of the keys to read any category.
+    pub fn with_keys(mut self, keys: Vec<String>) -> FeedProvider {
+        self.keys = Rc::new(keys);
+        self
+    }
+
+    /// Whether any category needs a key to be read.
+    pub fn requires_keys(&self) -> bool {
+        !self.keys.is_empty()
+            || self
+                .categories
+                .values()
+                .any(|category| !category.keys.is_empty())
+    }
+
+    /// The keys that give access to a category, or None if there is no such category.
+    pub fn category_keys(&self, category_name: &str) -> Option<Vec<&str>> {
+        let category = self.categories.get(category_name)?;
+        Some(
+            self.keys
+                .iter()
+                .chain(category.keys.iter())
+                .map(String::as_str)
+                .collect(),
+        )
+    }
+
+    /// Merge the feeds of a category, with a self link to the category under the base url.
+    pub async fn feed_by_category(&self, category_name: &str, base_url: &Url) -> Result<Feed> {
         let category = self
             .categories
             .get(category_name)
-            .ok_or_else(|| Error::msg(format!("Failed to find feed category {}", category_name)))?;
+            .ok_or_else(|| CategoryError::UnknownCategory(category_name.into()))?;
 
-        let feed_results = self.category_feeds(category).await;
+        let feed_results = self.category_feeds(category, base_url).await;
         let feeds = FeedProvider::discard_err_feeds(feed_results, category_name);
+        if feeds.is_empty() && !category.sources.is_empty() {
+            return Err(CategoryError::AllSourcesFailed(category_name.into()).into());
+        }
 
         Ok(merge_feeds(
             category_name.into(),
-            format!(
-                "https://feedragon.privacy.qvarford.net/feeds/{}/atom.xml",
-                category_name
-            )[..]
-                .try_into()?,
+            base_url
+                .join(&format!("feeds/{}/atom.xml", category_name))
+                .context("Failed to create category self link")?,
             feeds,
+            &category.merge_options,
         ))
     }
 
     async fn category_feeds<'a>(
         &'a self,
         category: &'a Category,
+        base_url: &'a Url,
     ) -> impl Iterator<Item = Result<Feed>> {
         type Handle = JoinHandle<Result<Feed>>;
         let mut feed_results: Vec<Handle> = vec![];
-        for url in category.feed_urls.iter() {
+        for source in category.sources.iter() {
             let future = FeedProvider::get_feed(
                 self.http_client.clone(),
                 self.feed_deserializer.clone(),
-                url.clone(),
+                source.clone(),
+                category.skip_invalid_entries,
+                self.rewrite_rules.clone(),
+                base_url.clone(),
             );
             feed_results.push(task::spawn_local(future));
         }
 
         let results = join_all(feed_results).await;
-        let flattened_results = results.into_iter().map(|rr| Ok(rr.map_err(Error::new)??));
-        flattened_results
+        results.into_iter().map(|rr| rr.map_err(Error::new)?)
     }
 
     async fn get_feed(
         http_client: Rc<dyn HttpClient>,
         deserializer: Rc<dyn FeedDeserializer>,
-        url: Url,
+        source: Source,
+        skip_invalid_entries: bool,
+        rewrite_rules: Rc<Vec<RewriteRule>>,
+        base_url: Url,
     ) -> Result<Feed> {
-        let bytes = http_client
-            .get_bytes(&url)
-            .await
-            .with_context(|| format!("Failed downloading feed {} as part of category", url))?;
-        let mut feed = deserializer
-            .parse_feed_from_bytes(bytes.as_ref())
-            .with_context(|| format!("Failed to parse feed {} as part of category", url))?;
+        let url = source.url.clone();
+        let resource = http_client.get_resource(&url).await.with_context(|| {
+            format!(
+                "Failed downloading feed {} as part of category",
+                Redacted(&url)
+            )
+        })?;
+        let parsed = deserializer
+            .parse_feed_with_content_type(
+                resource.bytes.as_ref(),
+                &url,
+                resource.content_type.as_deref(),
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to parse feed {} as part of category",
+                    Redacted(&url)
+                )
+            })?;
+        let mut feed = if skip_invalid_entries {
+            for warning in parsed.warnings.iter() {
+                log::warn!("Invalid entry in feed {}. {}", Redacted(&url), warning);
+            }
+            parsed.feed
+        } else {
+            parsed.into_strict().with_context(|| {
+                format!(
+                    "Failed to parse feed {} as part of category",
+                    Redacted(&url)
+                )
+            })?
+        };
         for entry in feed.entries.iter_mut() {
-            entry.id = entry
-                .id
-                .replace("www.reddit.com", "libreddit.privacy.qvarford.net");
+            rewrite(entry, &rewrite_rules);
+        }
+        feed.entries
+            .retain(|entry| is_included(entry, &source.filters));
+        let transformer = FeedTransformer {
+            http_client,
+            base_url,
+        };
+        for transformation in source.transformers {
+            feed = transformer.transform(feed, transformation).await;
+        }
+        if let Some(name) = source.name {
+            feed.title = name;
         }
         Ok(feed)
     }
@@ -125,7 +246,7 @@ impl FeedProvider {
             match feed_result {
                 Ok(feed) => Some(feed),
                 Err(err) => {
-                    log::warn!("Failed to fetch feed as part of category {}. It will not be part of the next category feed.\n{:#?}", category_name, err);
+                    log::warn!("Failed to fetch feed as part of category {}. It will not be part of the next category feed.\n{}", category_name, redact_text(&format!("{:#?}", err)));
                     None
                 }
             }
@@ -136,7 +257,19 @@ impl FeedProvider {
 
 #[derive(Clone)]
 struct Category {
-    feed_urls: Vec<Url>,
+    sources: Vec<Source>,
+    merge_options: MergeOptions,
+    skip_invalid_entries: bool,
+    keys: Vec<String>,
+}
+
+#[derive(Clone)]
+struct Source {
+    url: Url,
+    name: Option<String>,
+    /// The filters of the category followed by the filters of the source itself.
+    filters: Vec<FilterRule>,
+    transformers: Vec<Transformation>,
 }
 
 fn try_all<T: Sized, E, I: Iterator<Item = Result<T, E>> + Sized>(it: I) -> Result<IntoIter<T>, E> {
@@ -4,6 +4,7 @@ use std::str;
 use crate::{
     feed::{model::Entry, Feed},
     http_client::HttpClient,
+    redact::{redact_url_str, Redacted},
 };
 
 use anyhow::{Context, Error, Result};
@@ -11,14 +12,29 @@ use futures::{stream, FutureExt, Stream, StreamExt};
 use log::warn;
 use reqwest::Url;
 use scraper::{Html, Selector};
+use serde_derive::Deserialize;
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Transformation {
+    /// Replace every entry with one entry per image on the page it links to.
+    ExtractImages,
+}
 
 pub struct FeedTransformer {
     pub http_client: Rc<dyn HttpClient>,
+    /// The public url of feedragon, which images that need a redirect are proxied through.
+    pub base_url: Url,
 }
 
 impl FeedTransformer {
+    pub async fn transform(&self, feed: Feed, transformation: Transformation) -> Feed {
+        match transformation {
+            Transformation::ExtractImages => self.extract_images_from_feed(feed).await,
+        }
+    }
+
     pub async fn extract_images_from_feed(&self, feed: Feed) -> Feed {
-        let feed = feed;
         let stream = stream::iter(feed.entries).flat_map(|e| self.extract_images_from_entry(e));
 
         let entries: Vec<Entry> = stream
@@ -38,12 +54,12 @@ impl FeedTransformer {
     }
 
     fn extract_images_from_entry(&self, e: Entry) -> impl Stream<Item = Vec<Entry>> + '_ {
-        let id = e.id.clone();
-        let links = self.extract_images_from_page(e.id).into_stream();
+        let link = e.link.clone();
+        let links = self.extract_images_from_page(e.link.clone()).into_stream();
 
         let entries = links.map(move |links_result| match links_result {
             Err(error) => {
-                warn!("Could not extract images from {id}. Error: {error}");
+                warn!("Could not extract images from {link}. Error: {error}");
                 vec![]
             }
             Ok(links) => links
@@ -58,6 +74,10 @@ impl FeedTransformer {
                     summary: e.summary.clone(),
                     title: e.title.clone(),
                     updated: e.updated,
+                    published: e.published,
+                    author: e.author.clone(),
+                    sources: e.sources.clone(),
+                    media: e.media.clone(),
                 })
                 .collect::<Vec<_>>(),
         });
@@ -65,32 +85,37 @@ impl FeedTransformer {
     }
 
     async fn extract_images_from_page(&self, url: String) -> Result<Vec<Url>> {
-        let url: Url = Url::try_from(url.as_str())
-            .with_context(|| format!("Invalid link {url} during image extraction."))?;
+        let url: Url = Url::try_from(url.as_str()).with_context(|| {
+            format!(
+                "Invalid link {} during image extraction.",
+                redact_url_str(&url)
+            )
+        })?;
         let bytes = self.http_client.get_bytes(&url).await?;
-        let content: &str =
-            str::from_utf8(&bytes).with_context(|| format!("Page at {url} is not valid utf8"))?;
+        let content: &str = str::from_utf8(&bytes)
+            .with_context(|| format!("Page at {} is not valid utf8", Redacted(&url)))?;
         let html = Html::parse_document(content);
 
-        let image_links = FeedTransformer::scrape_images_from_html(&url, html)?;
+        let image_links = self.scrape_images_from_html(&url, html)?;
         let r: Result<Vec<Url>> = image_links
             .into_iter()
             .map(|s| -> Result<Url> {
-                Url::try_from(s?.as_str())
-                    .with_context(|| format!("Invalid link {url} during image extraction."))
+                Url::try_from(s?.as_str()).with_context(|| {
+                    format!("Invalid link {} during image extraction.", Redacted(&url))
+                })
             })
             .collect();
         r
     }
 
-    fn scrape_images_from_html(url: &Url, html: Html) -> Result<Vec<Result<String>>> {
+    fn scrape_images_from_html(&self, url: &Url, html: Html) -> Result<Vec<Result<String>>> {
         // TODO: Nitter videos are kinda bad, maybe skip them to begin with?
         // TODO: save-to-mega can't really handle hls videos either way it seems like. webm works.
         // TODO: Don't use og:image for libreddit since it's only a thumb.
         // TODO: Think about abstracting the common parts if there are more cases to handle in the future.
         if url.to_string().contains("nitter") {
             let selector = Selector::parse(r#"meta[property="og:image"]"#)
-                .or_else(|e| Err(Error::msg(format!("Could not parse selector {e:?}"))))?;
+                .map_err(|e| Error::msg(format!("Could not parse selector {e:?}")))?;
             let image_links = html.select(&selector).map(|element_ref| {
                 element_ref
                     .value()
@@ -101,34 +126,33 @@ impl FeedTransformer {
             Ok(image_links.collect())
         } else {
             let single_image_selector = Selector::parse(r#".post_media_image"#)
-                .or_else(|e| Err(Error::msg(format!("Could not parse selector {e:?}"))))?;
+                .map_err(|e| Error::msg(format!("Could not parse selector {e:?}")))?;
             let multiple_image_selector = Selector::parse(r#".gallery a"#)
-                .or_else(|e| Err(Error::msg(format!("Could not parse selector {e:?}"))))?;
+                .map_err(|e| Error::msg(format!("Could not parse selector {e:?}")))?;
 
             let image_links = html
                 .select(&single_image_selector)
                 .chain(html.select(&multiple_image_selector))
                 .map(|element_ref| {
-                    element_ref
-                        .value()
-                        .attr("href")
-                        .map(|href| format!("https://libreddit.privacy.qvarford.net{href}"))
-                        // TODO: Use a custom website to browse images that can handle image urls with query parameters.
-                        .map(|href| {
-                            if href.contains("preview/external-pre") {
-                                let url = Url::try_from(href.as_str()).unwrap();
-                                let query =
-                                    url.query().map(|q| base64::encode(q)).unwrap_or_default();
-                                let path = url.path();
-                                format!("https://feedragon.privacy.qvarford.net/libreddit/ep/{query}{path}")
-                            } else {
-                                href
-                            }
-                        })
-                        .map(|href| href.replace("preview/pre", "img"))
-                        .ok_or_else(|| {
-                            Error::msg("Missing content attribute for og:image property")
-                        })
+                    let href = element_ref.value().attr("href").ok_or_else(|| {
+                        Error::msg("Missing content attribute for og:image property")
+                    })?;
+                    // The links are relative to the libreddit instance the page is from.
+                    let image_url = url.join(href).with_context(|| {
+                        format!("Invalid image link {href} on {}", Redacted(url))
+                    })?;
+                    // TODO: Use a custom website to browse images that can handle image urls with query parameters.
+                    let href = if href.contains("preview/external-pre") {
+                        let query = image_url.query().map(base64::encode).unwrap_or_default();
+                        let path = image_url.path();
+                        self.base_url
+                            .join(&format!("libreddit/ep/{query}{path}"))
+                            .context("Failed to create libreddit proxy link")?
+                            .to_string()
+                    } else {
+                        image_url.to_string()
+                    };
+                    Ok(href.replace("preview/pre", "img"))
                 });
             Ok(image_links.collect())
         }
@@ -316,6 +340,9 @@ mod test {
         let http_client = HashMapHttpClient { hash_map: page_map };
         FeedTransformer {
             http_client: Rc::new(http_client),
+            base_url: "https://feedragon.privacy.qvarford.net/"
+                .try_into()
+                .unwrap(),
         }
     }
 
@@ -328,13 +355,13 @@ mod test {
             entries: ids
                 .into_iter()
                 .map(|id| Entry {
-                    id: id.into(),
-                    summary: "".into(),
-                    title: "".into(),
-                    link: id.into(),
                     updated: DateTime::parse_from_rfc3339("2022-03-22T07:26:01+00:00")
                         .unwrap()
                         .into(),
+                    published: DateTime::parse_from_rfc3339("2022-03-22T07:00:09+00:00")
+                        .unwrap()
+                        .into(),
+                    ..Entry::for_test(id)
                 })
                 .collect(),
         }
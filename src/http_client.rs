@@ -1,34 +1,198 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Error, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
-use reqwest::Url;
+use reqwest::{header, StatusCode, Url};
+
+use crate::redact::Redacted;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpResource {
+    pub bytes: Bytes,
+    pub content_type: Option<String>,
+    /// Validators for asking the server whether the resource changed since.
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
 
 #[async_trait(?Send)]
 pub trait HttpClient {
     async fn get_bytes(&self, url: &Url) -> Result<Bytes>;
+
+    /// Like get_bytes, but also includes the response headers we care about.
+    async fn get_resource(&self, url: &Url) -> Result<HttpResource> {
+        let bytes = self.get_bytes(url).await?;
+        Ok(HttpResource {
+            bytes,
+            content_type: None,
+            etag: None,
+            last_modified: None,
+        })
+    }
+
+    /// Like get_resource, but None when the server says the previous response is still up to date.
+    async fn get_resource_if_modified(
+        &self,
+        url: &Url,
+        _previous: &HttpResource,
+    ) -> Result<Option<HttpResource>> {
+        Ok(Some(self.get_resource(url).await?))
+    }
 }
 
-pub struct ReqwestHttpClient {}
+#[derive(Default)]
+pub struct ReqwestHttpClient {
+    /// Extra headers to send when requesting a url.
+    pub headers: HashMap<Url, HashMap<String, String>>,
+}
 
 #[async_trait(?Send)]
 impl HttpClient for ReqwestHttpClient {
     async fn get_bytes(&self, url: &Url) -> Result<Bytes> {
+        Ok(self.get_resource(url).await?.bytes)
+    }
+
+    async fn get_resource(&self, url: &Url) -> Result<HttpResource> {
+        self.request(url, None)
+            .await?
+            .ok_or_else(|| Error::msg("Got 304 Not Modified for an unconditional request"))
+    }
+
+    async fn get_resource_if_modified(
+        &self,
+        url: &Url,
+        previous: &HttpResource,
+    ) -> Result<Option<HttpResource>> {
+        self.request(url, Some(previous)).await
+    }
+}
+
+impl ReqwestHttpClient {
+    async fn request(
+        &self,
+        url: &Url,
+        previous: Option<&HttpResource>,
+    ) -> Result<Option<HttpResource>> {
         let client = reqwest::ClientBuilder::new()
             .tcp_keepalive(Some(Duration::from_secs(60)))
             .connect_timeout(Duration::from_secs(60))
             .build()
             .context("Failed to create client")?;
 
-        let body = client
-            .get(url.clone())
+        let mut request = client.get(url.clone());
+        for (name, value) in self.headers.get(url).into_iter().flatten() {
+            request = request.header(name, value);
+        }
+        if let Some(etag) = previous.and_then(|previous| previous.etag.as_ref()) {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = previous.and_then(|previous| previous.last_modified.as_ref()) {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request
             .send()
             .await
-            .with_context(|| format!("Failed to download resource {}", url))?
+            // The url is added back redacted, since it can contain secrets.
+            .map_err(reqwest::Error::without_url)
+            .with_context(|| format!("Failed to download resource {}", Redacted(url)))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        // Error pages, e.g. from a rate limited instance, shouldn't replace the cached feed.
+        let response = response
+            .error_for_status()
+            .map_err(reqwest::Error::without_url)
+            .with_context(|| format!("Failed to download resource {}", Redacted(url)))?;
+        let response_header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let content_type = response_header(header::CONTENT_TYPE);
+        let etag = response_header(header::ETAG);
+        let last_modified = response_header(header::LAST_MODIFIED);
+        let bytes = response
             .bytes()
             .await
+            .map_err(reqwest::Error::without_url)
             .context("Failed to extract byte request body")?;
-        Ok(body)
+        Ok(Some(HttpResource {
+            bytes,
+            content_type,
+            etag,
+            last_modified,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+
+    // Answers with 304 when the request has the validators of the feed it serves.
+    async fn feed(request: HttpRequest) -> HttpResponse {
+        let validator = |name| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        if validator(header::IF_NONE_MATCH) == Some(r#""v1""#)
+            && validator(header::IF_MODIFIED_SINCE) == Some("Sun, 08 May 2022 07:04:26 GMT")
+        {
+            HttpResponse::NotModified().finish()
+        } else {
+            HttpResponse::Ok()
+                .insert_header((header::ETAG, r#""v1""#))
+                .insert_header((header::LAST_MODIFIED, "Sun, 08 May 2022 07:04:26 GMT"))
+                .body("<rss />")
+        }
+    }
+
+    fn start_server() -> Url {
+        let server = HttpServer::new(|| {
+            App::new().route("/rss", web::get().to(feed)).route(
+                "/limited",
+                web::get().to(|| async { HttpResponse::TooManyRequests().body("Slow down") }),
+            )
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_rt::spawn(server.run());
+        Url::parse(&format!("http://{}/", address)).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn validators_are_sent_and_not_modified_is_none() {
+        let url = start_server().join("rss").unwrap();
+        let client = ReqwestHttpClient::default();
+
+        let resource = client.get_resource(&url).await.unwrap();
+        assert_eq!(resource.etag.as_deref(), Some(r#""v1""#));
+
+        assert_eq!(
+            client
+                .get_resource_if_modified(&url, &resource)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[actix_rt::test]
+    async fn error_statuses_are_errors() {
+        let url = start_server().join("limited").unwrap();
+        let client = ReqwestHttpClient::default();
+
+        let error = client.get_resource(&url).await.unwrap_err();
+
+        assert!(format!("{:#}", error).contains("429"), "{:#}", error);
     }
 }
@@ -1,49 +1,164 @@
-use std::{fs::read_to_string, rc::Rc, sync::Arc};
+use std::{cell::RefCell, env, fs::read_to_string, process, rc::Rc, sync::Arc};
 
 mod cache;
 mod caching_http_client;
+mod command_line;
 mod config;
 mod feed;
 mod feed_provider;
 mod feed_transformer;
 mod http_client;
+mod redact;
+mod reload;
 mod server;
 
+use anyhow::{Context, Result};
 use caching_http_client::CachingHttpClient;
-use config::Config;
+use command_line::{CommandLine, USAGE};
+use config::{Config, ServerConfig, SourceConfig};
 use feed::default_feed_deserializer;
 use feed_provider::FeedProvider;
 use http_client::ReqwestHttpClient;
+use redact::{redact_text, set_secret_params};
+use reload::{watch_config, SharedConfig};
 use reqwest::Url;
 use server::start_server;
 
 extern crate serde_derive;
 
-fn thread_local_feed_provider(config: Arc<Config>) -> FeedProvider {
-    let http_client = ReqwestHttpClient {};
-    let feed_deserializer = Rc::new(default_feed_deserializer());
-    let feed_urls = config
+/// Build the feed provider of a worker, keeping the responses cached by the provider of an older config.
+fn thread_local_feed_provider(
+    config: &Config,
+    server_config: &ServerConfig,
+    previous_http_client: Option<&CachingHttpClient>,
+) -> (FeedProvider, Rc<CachingHttpClient>) {
+    let sources: Vec<(Url, &SourceConfig)> = config
         .categories
-        .iter()
-        .flat_map(|(_, url_strings)| url_strings)
-        .map(|s| Url::parse(s).unwrap());
+        .values()
+        .flat_map(|category| category.sources.iter())
+        .filter(|source| source.enabled)
+        .map(|source| (Url::parse(&source.url).unwrap(), source))
+        .collect();
+    let http_client = ReqwestHttpClient {
+        headers: sources
+            .iter()
+            .filter(|(_, source)| !source.headers.is_empty())
+            .map(|(url, source)| (url.clone(), source.headers.clone()))
+            .collect(),
+    };
+    let feed_deserializer = Rc::new(default_feed_deserializer());
 
-    let http_client =
-        CachingHttpClient::new(Rc::new(http_client), chrono::Duration::hours(1), feed_urls);
+    let http_client = CachingHttpClient::new(
+        Rc::new(http_client),
+        config.server.cache_ttl(),
+        sources.iter().map(|(url, _)| url.clone()),
+    )
+    .with_expiration_durations(
+        sources
+            .iter()
+            .filter_map(|(url, source)| Some((url.clone(), source.cache_ttl()?))),
+    );
+    let http_client = match previous_http_client {
+        Some(previous) => http_client.with_cache_of(previous),
+        None => http_client,
+    };
     let http_client = Rc::new(http_client);
     let provider = FeedProvider::from_categories_and_http_client_and_feed_deserializer(
         config.categories.clone(),
-        http_client,
+        http_client.clone(),
         feed_deserializer,
     )
-    .unwrap();
-    provider
+    .unwrap()
+    .with_rewrite_rules(config.rewrite_rules(server_config).unwrap())
+    .with_keys(config.keys.clone());
+    (provider, http_client)
+}
+
+/// Gives a worker the feed provider of the current config, rebuilding it after the config is reloaded.
+/// Server settings other than the cache duration are the ones it started with, since only those are reloaded.
+fn reloading_feed_provider(
+    shared_config: SharedConfig,
+    server_config: ServerConfig,
+) -> impl Fn() -> FeedProvider {
+    type Current = (Arc<Config>, FeedProvider, Rc<CachingHttpClient>);
+    let current: RefCell<Option<Current>> = RefCell::new(None);
+    move || {
+        let config = shared_config.current();
+        let mut current = current.borrow_mut();
+        if let Some((current_config, provider, _)) = current.as_ref() {
+            if Arc::ptr_eq(current_config, &config) {
+                return provider.clone();
+            }
+        }
+        let previous_http_client = current.as_ref().map(|(_, _, http_client)| http_client);
+        let (provider, http_client) = thread_local_feed_provider(
+            &config,
+            &server_config,
+            previous_http_client.map(Rc::as_ref),
+        );
+        *current = Some((config, provider.clone(), http_client));
+        provider
+    }
+}
+
+fn config_path(command_line: &CommandLine) -> String {
+    command_line
+        .config_path
+        .clone()
+        .or_else(|| env::var("FEEDRAGON_CONFIG").ok())
+        .unwrap_or_else(|| "feedragon.toml".into())
+}
+
+fn read_config(command_line: &CommandLine) -> Result<Config> {
+    let path = config_path(command_line);
+    let toml = read_to_string(&path).with_context(|| format!("Failed to read config {}", path))?;
+    let mut config = Config::from_toml_str(&toml)?;
+    // Set right away, so the errors below are masked with them as well.
+    set_secret_params(&config.secret_params);
+
+    config.server.set_from_env(env::vars())?;
+    for (name, value) in command_line.server_settings.iter() {
+        config
+            .server
+            .set(name, value)
+            .with_context(|| format!("Invalid option --{}", name.replace('_', "-")))?;
+    }
+    config.server.parsed_public_base_url()?;
+    config.rewrite_rules(&config.server)?;
+    // Catch everything that would fail when a worker builds its provider.
+    FeedProvider::from_categories_and_http_client_and_feed_deserializer(
+        config.categories.clone(),
+        Rc::new(ReqwestHttpClient::default()),
+        Rc::new(default_feed_deserializer()),
+    )?;
+    Ok(config)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
-    let config =
-        Arc::new(Config::from_toml_str(&read_to_string("feedragon.toml").unwrap()).unwrap());
-    start_server(8080, move || thread_local_feed_provider(config.clone())).await
+    let command_line = CommandLine::parse(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        process::exit(2)
+    });
+    if command_line.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let config = read_config(&command_line).unwrap_or_else(|err| {
+        eprintln!("{}", redact_text(&format!("{:#}", err)));
+        process::exit(1)
+    });
+    let shared_config = SharedConfig::new(config);
+    let server_config = shared_config.current().server.clone();
+    watch_config(
+        shared_config.clone(),
+        config_path(&command_line),
+        move || read_config(&command_line),
+    );
+    let startup_server_config = server_config.clone();
+    start_server(&server_config, move || {
+        reloading_feed_provider(shared_config.clone(), startup_server_config.clone())
+    })
+    .await
 }
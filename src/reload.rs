@@ -0,0 +1,140 @@
+use std::fs;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use crate::config::{Config, ServerConfig};
+use crate::redact::{redact_text, set_secret_params};
+
+// How often the config file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The config shared by every worker, which is replaced as a whole when it's reloaded.
+#[derive(Clone)]
+pub struct SharedConfig {
+    config: Arc<RwLock<Arc<Config>>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        set_secret_params(&config.secret_params);
+        SharedConfig {
+            config: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Replace the config with a newly read one, or keep the current one when it's invalid.
+    pub fn reload<F: FnOnce() -> Result<Config>>(&self, read_config: F) -> bool {
+        match read_config() {
+            Ok(config) => {
+                if !only_cache_ttl_changed(&self.current().server, &config.server) {
+                    log::warn!("Only cache_ttl_minutes of the server settings is reloaded, restart to apply the rest.");
+                }
+                set_secret_params(&config.secret_params);
+                *self.config.write().unwrap() = Arc::new(config);
+                log::info!("Reloaded the config.");
+                true
+            }
+            Err(err) => {
+                // Reading the config sets its secret params, so go back to the ones of the current config.
+                set_secret_params(&self.current().secret_params);
+                log::error!(
+                    "Failed to reload the config, the current one is kept. {}",
+                    redact_text(&format!("{:#}", err))
+                );
+                false
+            }
+        }
+    }
+}
+
+fn only_cache_ttl_changed(old: &ServerConfig, new: &ServerConfig) -> bool {
+    let new = ServerConfig {
+        cache_ttl_minutes: old.cache_ttl_minutes,
+        ..new.clone()
+    };
+    *old == new
+}
+
+/// Reload the config when its file is modified, or when the process receives SIGHUP.
+pub fn watch_config<F: Fn() -> Result<Config> + 'static>(
+    shared_config: SharedConfig,
+    path: String,
+    read_config: F,
+) {
+    let read_config = Rc::new(read_config);
+
+    let poll_config = shared_config.clone();
+    let poll_read_config = read_config.clone();
+    actix_rt::spawn(async move {
+        let mut last_modified = modified(&path);
+        let mut interval = actix_rt::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            // Editors can briefly remove the file while saving it, so wait for it to come back.
+            let modified = modified(&path);
+            if modified.is_some() && modified != last_modified {
+                last_modified = modified;
+                poll_config.reload(|| poll_read_config());
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    actix_rt::spawn(async move {
+        use actix_rt::signal::unix::{signal, SignalKind};
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(err) => {
+                log::error!("Failed to listen for SIGHUP, the config is only reloaded when its file changes. {}", err);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            shared_config.reload(|| read_config());
+        }
+    });
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Error;
+
+    fn config(port: u16) -> Result<Config> {
+        let mut config = Config::from_toml_str("[categories]")?;
+        config.server.port = port;
+        Ok(config)
+    }
+
+    #[test]
+    fn valid_configs_replace_the_current_one() {
+        let shared_config = SharedConfig::new(config(8080).unwrap());
+        let before = shared_config.current();
+
+        assert!(shared_config.reload(|| config(8081)));
+
+        assert!(!Arc::ptr_eq(&before, &shared_config.current()));
+        assert_eq!(shared_config.current().server.port, 8081);
+    }
+
+    #[test]
+    fn invalid_configs_keep_the_current_one() {
+        let shared_config = SharedConfig::new(config(8080).unwrap());
+        let before = shared_config.current();
+
+        assert!(!shared_config.reload(|| Err(Error::msg("Invalid config"))));
+
+        assert!(Arc::ptr_eq(&before, &shared_config.current()));
+    }
+}
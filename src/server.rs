@@ -1,30 +1,92 @@
-use crate::feed_provider::FeedProvider;
+use crate::config::ServerConfig;
+use crate::feed::Feed;
+use crate::feed_provider::{CategoryError, FeedProvider};
 use crate::feed_transformer::FeedTransformer;
-use actix_web::http::header;
+use crate::redact::redact_text;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{
+    ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::http::{header, StatusCode};
 use actix_web::web::ServiceConfig;
-use actix_web::{get, web, App, HttpResponse, HttpServer};
-use actix_web::{Responder, ResponseError};
-use anyhow;
+use actix_web::ResponseError;
+use actix_web::{get, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer};
 use anyhow::{Context, Error, Result};
 use derive_more::Display;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use md5::{Digest, Md5};
 use serde_derive::Deserialize;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+use url::Url;
 
-#[derive(Display, Debug)]
-struct LoggingError {
-    err: Error,
+/// Errors sent to clients, which only say what went wrong in general.
+/// The full error is logged when it's created instead.
+#[derive(Display, Debug, PartialEq)]
+enum ServerError {
+    #[display(fmt = "Not found")]
+    NotFound,
+    #[display(fmt = "Bad request: {}", _0)]
+    BadRequest(&'static str),
+    #[display(fmt = "None of the sources could be fetched")]
+    BadGateway,
+    #[display(fmt = "Internal server error")]
+    Internal,
 }
 
-impl ResponseError for LoggingError {}
+impl ResponseError for ServerError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ServerError::NotFound => StatusCode::NOT_FOUND,
+            ServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ServerError::BadGateway => StatusCode::BAD_GATEWAY,
+            ServerError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
 
-impl From<Error> for LoggingError {
-    fn from(err: Error) -> LoggingError {
-        log::error!("{:#?}", err);
-        LoggingError { err }
+impl From<Error> for ServerError {
+    fn from(err: Error) -> ServerError {
+        let message = redact_text(&format!("{:#?}", err));
+        match err.downcast_ref::<CategoryError>() {
+            Some(CategoryError::UnknownCategory(_)) => {
+                log::debug!("{}", message);
+                ServerError::NotFound
+            }
+            Some(CategoryError::AllSourcesFailed(_)) => {
+                log::error!("{}", message);
+                ServerError::BadGateway
+            }
+            None => {
+                log::error!("{}", message);
+                ServerError::Internal
+            }
+        }
     }
 }
 
 struct AppState {
-    provider: FeedProvider,
+    /// Gives the provider of the current config, which changes when the config is reloaded.
+    provider: Rc<dyn Fn() -> FeedProvider>,
+    public_base_url: Option<Url>,
+    libreddit_url: Option<Url>,
+}
+
+impl AppState {
+    /// The configured public base url, or the one the request was made to when there is none.
+    /// The host and scheme take X-Forwarded-Host and X-Forwarded-Proto into account.
+    fn base_url(&self, req: &HttpRequest) -> Result<Url> {
+        match &self.public_base_url {
+            Some(url) => Ok(url.clone()),
+            None => {
+                let info = req.connection_info();
+                let url = format!("{}://{}/", info.scheme(), info.host());
+                Url::parse(&url).with_context(|| format!("Invalid request base url {}", url))
+            }
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -32,29 +94,278 @@ struct Query {
     extract: Option<String>,
 }
 
-#[get("/feeds/{name}/atom.xml")]
+#[derive(Deserialize)]
+struct KeyQuery {
+    key: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Access {
+    Granted,
+    /// No key was given for a category that needs one.
+    Unauthorized,
+    /// The key doesn't give access, which looks the same as a category that doesn't exist.
+    NotFound,
+}
+
+/// Checks the key of requests to a category, given as ?key= or an Authorization: Bearer header.
+struct RequireKey;
+
+impl<S, B> Transform<S, ServiceRequest> for RequireKey
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RequireKeyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireKeyMiddleware { service }))
+    }
+}
+
+struct RequireKeyMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireKeyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let response = match request_access(&req) {
+            Access::Granted => {
+                let response = self.service.call(req);
+                return Box::pin(async move { Ok(response.await?.map_into_left_body()) });
+            }
+            Access::Unauthorized => HttpResponse::Unauthorized()
+                .append_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .finish(),
+            Access::NotFound => HttpResponse::NotFound().finish(),
+        };
+        Box::pin(ready(Ok(req.into_response(response).map_into_right_body())))
+    }
+}
+
+// Auth schemes are case-insensitive, so bearer works as well as Bearer.
+fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.split_once(' ')?;
+    Some(token.trim()).filter(|_| scheme.eq_ignore_ascii_case("bearer"))
+}
+
+fn request_access(req: &ServiceRequest) -> Access {
+    let provider = match req.app_data::<web::Data<AppState>>() {
+        Some(state) => (state.provider)(),
+        None => return Access::NotFound,
+    };
+    let category_name = req.match_info().get("name").unwrap_or_default();
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(bearer_token)
+        .map(String::from);
+    let key = bearer.or_else(|| {
+        web::Query::<KeyQuery>::from_query(req.query_string())
+            .ok()
+            .and_then(|query| query.into_inner().key)
+    });
+    access(&provider, category_name, key.as_deref())
+}
+
+/// Categories without keys are public, unless there are global keys.
+/// Unknown categories are treated like ones that need a key, so their names can't be probed.
+fn access(provider: &FeedProvider, category_name: &str, key: Option<&str>) -> Access {
+    if !provider.requires_keys() {
+        return Access::Granted;
+    }
+    let category_keys = provider.category_keys(category_name);
+    match (category_keys, key) {
+        (Some(keys), _) if keys.is_empty() => Access::Granted,
+        (_, None) => Access::Unauthorized,
+        (Some(keys), Some(key)) if keys.iter().any(|valid| constant_time_eq(valid, key)) => {
+            Access::Granted
+        }
+        _ => Access::NotFound,
+    }
+}
+
+/// Compare keys in a time that only depends on their lengths, so they can't be guessed byte by byte.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |difference, (x, y)| difference | (x ^ y))
+        == 0
+}
+
+#[get("/atom.xml")]
 async fn feed_category(
     info: web::Path<String>,
     state: web::Data<AppState>,
     query: web::Query<Query>,
-) -> Result<String, LoggingError> {
+    req: HttpRequest,
+) -> Result<HttpResponse, ServerError> {
     let category_name = &info.into_inner();
-    let feed = state.provider.feed_by_category(category_name).await?;
-    let feed = if query.extract.as_ref().filter(|e| **e == "media").is_some() {
-        let transformer = FeedTransformer {
-            http_client: state.provider.http_client.clone(),
-        };
-        transformer.extract_images_from_feed(feed).await
-    } else {
-        feed
-    };
+    let feed = category_feed(category_name, &state, &query, &req).await?;
+    let version = FeedVersion::of(&feed, "application/atom+xml");
     let response_body = feed.serialize_to_string().with_context(|| {
         format!(
             "Failed to convert feed category {} to string",
             category_name
         )
     })?;
-    Ok(response_body)
+    Ok(conditional_response(&req, version, response_body))
+}
+
+#[get("/rss.xml")]
+async fn rss_feed_category(
+    info: web::Path<String>,
+    state: web::Data<AppState>,
+    query: web::Query<Query>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServerError> {
+    let category_name = &info.into_inner();
+    let mut feed = category_feed(category_name, &state, &query, &req).await?;
+    // The provider links to the atom feed, so point the self link at this endpoint instead.
+    feed.link = feed
+        .link
+        .join("rss.xml")
+        .context("Failed to create rss self link")?;
+    let version = FeedVersion::of(&feed, "application/rss+xml");
+    let response_body = feed.serialize_to_rss_string().with_context(|| {
+        format!(
+            "Failed to convert feed category {} to rss string",
+            category_name
+        )
+    })?;
+    Ok(conditional_response(&req, version, response_body))
+}
+
+#[get("/feed.json")]
+async fn json_feed_category(
+    info: web::Path<String>,
+    state: web::Data<AppState>,
+    query: web::Query<Query>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServerError> {
+    let category_name = &info.into_inner();
+    let mut feed = category_feed(category_name, &state, &query, &req).await?;
+    feed.link = feed
+        .link
+        .join("feed.json")
+        .context("Failed to create json feed self link")?;
+    let version = FeedVersion::of(&feed, "application/feed+json");
+    let response_body = feed.serialize_to_json_string().with_context(|| {
+        format!(
+            "Failed to convert feed category {} to json string",
+            category_name
+        )
+    })?;
+    Ok(conditional_response(&req, version, response_body))
+}
+
+/// Identifies the version of a feed, which is computed from the feed before it's serialized.
+/// The serialized feed also includes when it was generated, so the etag is weak.
+struct FeedVersion {
+    content_type: &'static str,
+    etag: EntityTag,
+    /// When the newest entry was updated, in whole seconds like the dates clients send back.
+    last_modified: Option<HttpDate>,
+}
+
+impl FeedVersion {
+    fn of(feed: &Feed, content_type: &'static str) -> FeedVersion {
+        let mut hasher = Md5::new();
+        hasher.update(content_type.as_bytes());
+        // The debug output has every field of the feed and its entries, quoted and separated,
+        // so a field added later is part of the etag as soon as it can be serialized.
+        write!(hasher, "{:?}", feed).expect("Writing to a hasher can't fail");
+        let last_modified = feed
+            .entries
+            .iter()
+            .map(|entry| entry.updated.timestamp())
+            .max()
+            .and_then(|seconds| u64::try_from(seconds).ok())
+            .map(|seconds| HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)));
+        FeedVersion {
+            content_type,
+            etag: EntityTag::new_weak(hex::encode(hasher.finalize())),
+            last_modified,
+        }
+    }
+}
+
+/// Respond with the serialized feed, or with 304 when the client already has this version of it.
+fn conditional_response(req: &HttpRequest, version: FeedVersion, body: String) -> HttpResponse {
+    let FeedVersion {
+        content_type,
+        etag,
+        last_modified,
+    } = version;
+    let not_modified = match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(etags)) => etags.iter().any(|other| other.weak_eq(&etag)),
+        // If-Modified-Since is only used by clients that don't send If-None-Match.
+        None => match (req.get_header::<IfModifiedSince>(), last_modified) {
+            (Some(IfModifiedSince(since)), Some(modified)) => modified <= since,
+            _ => false,
+        },
+    };
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.insert_header(ETag(etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if not_modified {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
+
+async fn category_feed(
+    category_name: &str,
+    state: &AppState,
+    query: &Query,
+    req: &HttpRequest,
+) -> Result<Feed, ServerError> {
+    let extract_media = match query.extract.as_deref() {
+        None => false,
+        Some("media") => true,
+        Some(_) => return Err(ServerError::BadRequest("extract only supports media")),
+    };
+    let base_url = state.base_url(req)?;
+    let provider = (state.provider)();
+    let feed = provider.feed_by_category(category_name, &base_url).await?;
+    let feed = if extract_media {
+        let transformer = FeedTransformer {
+            http_client: provider.http_client.clone(),
+            base_url,
+        };
+        transformer.extract_images_from_feed(feed).await
+    } else {
+        feed
+    };
+    Ok(feed)
 }
 
 #[derive(Deserialize)]
@@ -64,34 +375,65 @@ struct ExternalPreviewPath {
 }
 
 #[get("/libreddit/ep/{query_base64}/{tail:.*}")]
-async fn libreddit_redirect(info: web::Path<ExternalPreviewPath>) -> impl Responder {
+async fn libreddit_redirect(
+    info: web::Path<ExternalPreviewPath>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ServerError> {
+    let libreddit_url = state.libreddit_url.as_ref().ok_or(ServerError::NotFound)?;
     let tail = &info.tail;
-    let query = String::from_utf8(base64::decode(&info.query_base64).unwrap()).unwrap();
-    let value = format!("https://libreddit.privacy.qvarford.net/{tail}?{query}");
-    HttpResponse::SeeOther()
+    let query = base64::decode(&info.query_base64)
+        .ok()
+        .and_then(|query| String::from_utf8(query).ok())
+        .ok_or(ServerError::BadRequest(
+            "the query is not base64 encoded utf8",
+        ))?;
+    // The libreddit url always ends with a slash.
+    let value = format!("{libreddit_url}{tail}?{query}");
+    Ok(HttpResponse::SeeOther()
         .append_header((header::LOCATION, value))
-        .finish()
+        .finish())
 }
 
-pub async fn start_server<F: Clone + Send + 'static + Fn() -> FeedProvider>(
-    port: u16,
-    factory: F,
-) -> std::io::Result<()> {
-    HttpServer::new(move || App::new().configure(config_app(factory())))
-        .bind(("0.0.0.0", port))?
-        .workers(1)
-        .run()
-        .await
+pub async fn start_server<F, P>(config: &ServerConfig, factory: F) -> std::io::Result<()>
+where
+    F: Clone + Send + 'static + Fn() -> P,
+    P: Fn() -> FeedProvider + 'static,
+{
+    let invalid_url = |err: Error| std::io::Error::new(std::io::ErrorKind::InvalidInput, err);
+    let public_base_url = config.parsed_public_base_url().map_err(invalid_url)?;
+    let libreddit_url = config.parsed_libreddit_url().map_err(invalid_url)?;
+    HttpServer::new(move || {
+        App::new().configure(config_app(
+            Rc::new(factory()),
+            public_base_url.clone(),
+            libreddit_url.clone(),
+        ))
+    })
+    .bind((config.bind_address.as_str(), config.port))?
+    .workers(config.workers)
+    .run()
+    .await
 }
 
-fn config_app(provider: FeedProvider) -> Box<dyn Fn(&mut ServiceConfig)> {
+fn config_app(
+    provider: Rc<dyn Fn() -> FeedProvider>,
+    public_base_url: Option<Url>,
+    libreddit_url: Option<Url>,
+) -> Box<dyn Fn(&mut ServiceConfig)> {
     Box::new(move |cfg: &mut ServiceConfig| {
         cfg.app_data(web::Data::new(AppState {
             provider: provider.clone(),
+            public_base_url: public_base_url.clone(),
+            libreddit_url: libreddit_url.clone(),
         }))
-        .service(feed_category)
+        .service(
+            web::scope("/feeds/{name}")
+                .wrap(RequireKey)
+                .service(feed_category)
+                .service(rss_feed_category)
+                .service(json_feed_category),
+        )
         .service(libreddit_redirect);
-        ()
     })
 }
 
@@ -100,7 +442,8 @@ mod test {
     use anyhow::Result;
     use std::{collections::HashMap, rc::Rc};
 
-    use crate::{feed::default_feed_deserializer, http_client::HttpClient};
+    use crate::feed::model::{Author, Entry, Media, MediaKind};
+    use crate::{config::CategoryConfig, feed::default_feed_deserializer, http_client::HttpClient};
 
     use super::*;
     use actix_http::{
@@ -162,16 +505,35 @@ mod test {
     async fn start(
         category_to_short_names: HashMap<String, Vec<FeedShortName>>,
     ) -> impl Service<actix_http::Request, Response = ServiceResponse<BoxBody>, Error = actix_web::Error>
+    {
+        let public_base_url = "https://feedragon.privacy.qvarford.net/"
+            .try_into()
+            .unwrap();
+        start_with(
+            category_to_short_names,
+            Some(public_base_url),
+            vec![],
+            vec![],
+        )
+        .await
+    }
+
+    async fn start_with(
+        category_to_short_names: HashMap<String, Vec<FeedShortName>>,
+        public_base_url: Option<Url>,
+        keys: Vec<String>,
+        category_keys: Vec<(&str, &str)>,
+    ) -> impl Service<actix_http::Request, Response = ServiceResponse<BoxBody>, Error = actix_web::Error>
     {
         let url_to_content: HashMap<String, FeedShortName> = category_to_short_names
-            .iter()
-            .flat_map(|(_, short_names)| short_names)
+            .values()
+            .flatten()
             .map(|short_name| (short_name.url_string(), short_name.clone()))
             .collect();
         let http_client = Rc::new(HashMapHttpClient {
             hash_map: url_to_content,
         });
-        let categories: HashMap<String, Vec<String>> = category_to_short_names
+        let mut categories: HashMap<String, CategoryConfig> = category_to_short_names
             .into_iter()
             .map(|(category, short_names)| {
                 (
@@ -179,28 +541,46 @@ mod test {
                     short_names
                         .into_iter()
                         .map(|short_name| short_name.url_string())
-                        .collect(),
+                        .collect::<Vec<_>>()
+                        .into(),
                 )
             })
             .collect();
+        for (category, key) in category_keys {
+            categories.get_mut(category).unwrap().keys.push(key.into());
+        }
         let provider = FeedProvider::from_categories_and_http_client_and_feed_deserializer(
             categories,
             http_client,
             Rc::new(default_feed_deserializer()),
         )
-        .unwrap();
-        let app = init_service(App::new().configure(config_app(provider))).await;
+        .unwrap()
+        .with_keys(keys);
+        let app = init_service(App::new().configure(config_app(
+            Rc::new(move || provider.clone()),
+            public_base_url,
+            None,
+        )))
+        .await;
         app
     }
 
     async fn fetch_category(
         category: &str,
         category_to_short_names: HashMap<String, Vec<FeedShortName>>,
+    ) -> (StatusCode, String) {
+        fetch_category_file(category, "atom.xml", category_to_short_names).await
+    }
+
+    async fn fetch_category_file(
+        category: &str,
+        file: &str,
+        category_to_short_names: HashMap<String, Vec<FeedShortName>>,
     ) -> (StatusCode, String) {
         let app = start(category_to_short_names).await;
 
         let request = TestRequest::get()
-            .uri(&format!("/feeds/{}/atom.xml", category))
+            .uri(&format!("/feeds/{}/{}", category, file))
             .to_request();
         let response = app.call(request).await.unwrap();
 
@@ -258,7 +638,444 @@ mod test {
         assert!(
             string.contains("SmallAnt joined the discord call at the worst time"),
             "Expected to find an item from the first invidious feed"
+        );
+        assert!(
+            string.contains("<ns:published>2022-05-08T07:00:05+00:00</ns:published>")
+                && string.contains("<ns:updated>2022-05-08T07:04:26+00:00</ns:updated>"),
+            "Expected both published and updated dates of the invidious entry"
+        );
+        assert!(
+            string.contains("<ns:name>SmallAnt Clips</ns:name>"),
+            "Expected the invidious entry author to be preserved"
+        );
+        assert!(
+            string.contains("<ns:name>TieTuesday's Twitch video RSS</ns:name>"),
+            "Expected entries without an author to be attributed to their feed"
+        )
+    }
+
+    #[actix_rt::test]
+    pub async fn category_can_be_fetched_as_rss() {
+        let category_to_short_names = [(
+            "comedy".into(),
+            vec![
+                FeedShortName {
+                    value: "HardDriveMag".into(),
+                    feed_type: FeedType::Nitter,
+                },
+                FeedShortName {
+                    value: "invidious".into(),
+                    feed_type: FeedType::Invidious,
+                },
+            ],
+        )]
+        .into();
+
+        let (status_code, string) =
+            fetch_category_file("comedy", "rss.xml", category_to_short_names).await;
+
+        assert!(
+            status_code.is_success(),
+            "Should be possible to fetch valid feeds as rss."
+        );
+        assert!(
+            string.contains("<rss ") && string.contains(r#"version="2.0">"#),
+            "Expected an rss 2.0 root element"
+        );
+        assert!(
+            string.contains(
+                r#"<atom:link href="https://feedragon.privacy.qvarford.net/feeds/comedy/rss.xml" rel="self" type="application/rss+xml" />"#
+            ),
+            "Expected the self link to point at the rss endpoint"
+        );
+        assert!(
+            string.contains("<title>three anime articles in a row????</title>"),
+            "Expected to find an item from the nitter feed"
+        );
+        assert!(
+            string.contains("<dc:creator>@HardDriveMag</dc:creator>"),
+            "Expected the nitter item author to be preserved"
+        );
+        assert!(
+            string.contains(r#"<media:thumbnail url="https://invidious.privacy.qvarford.net/vi/FteDtniami0/mqdefault.jpg" />"#),
+            "Expected the invidious thumbnail to be preserved"
+        );
+        assert!(
+            string.contains("<title>SmallAnt joined the discord call at the worst time</title>"),
+            "Expected to find an item from the invidious feed"
+        );
+        assert!(
+            string.contains("<pubDate>Sun, 08 May 2022 07:00:05 +0000</pubDate>"),
+            "Expected entry publish dates to be formatted according to rfc 2822"
+        )
+    }
+
+    #[actix_rt::test]
+    pub async fn category_can_be_fetched_as_json_feed() {
+        let category_to_short_names = [(
+            "comedy".into(),
+            vec![FeedShortName {
+                value: "invidious".into(),
+                feed_type: FeedType::Invidious,
+            }],
+        )]
+        .into();
+
+        let (status_code, string) =
+            fetch_category_file("comedy", "feed.json", category_to_short_names).await;
+
+        assert!(
+            status_code.is_success(),
+            "Should be possible to fetch valid feeds as json."
+        );
+        let json: serde_json::Value = serde_json::from_str(&string).unwrap();
+        assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(
+            json["feed_url"],
+            "https://feedragon.privacy.qvarford.net/feeds/comedy/feed.json"
+        );
+        let item = json["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|item| item["title"] == "SmallAnt joined the discord call at the worst time")
+            .expect("Expected to find an item from the invidious feed");
+        assert_eq!(
+            item["url"],
+            "https://invidious.privacy.qvarford.net/watch?v=FteDtniami0"
+        );
+        assert_eq!(item["date_published"], "2022-05-08T07:00:05+00:00");
+        assert_eq!(item["date_modified"], "2022-05-08T07:04:26+00:00");
+    }
+
+    #[actix_rt::test]
+    pub async fn self_link_uses_the_forwarded_host_without_a_public_base_url() {
+        let category_to_short_names = [(
+            "comedy".into(),
+            vec![FeedShortName {
+                value: "HardDriveMag".into(),
+                feed_type: FeedType::Nitter,
+            }],
+        )]
+        .into();
+        let app = start_with(category_to_short_names, None, vec![], vec![]).await;
+
+        let request = TestRequest::get()
+            .uri("/feeds/comedy/atom.xml")
+            .insert_header(("X-Forwarded-Host", "feeds.example.org"))
+            .insert_header(("X-Forwarded-Proto", "https"))
+            .to_request();
+        let response = app.call(request).await.unwrap();
+        let bytes = body::to_bytes(response.into_body()).await.unwrap();
+        let string = String::from_utf8(bytes[..].into()).unwrap();
+
+        assert!(
+            string.contains(r#"href="https://feeds.example.org/feeds/comedy/atom.xml""#),
+            "Expected the self link to use the forwarded host, got {}",
+            string
+        );
+    }
+
+    async fn status(
+        app: &impl Service<
+            actix_http::Request,
+            Response = ServiceResponse<BoxBody>,
+            Error = actix_web::Error,
+        >,
+        request: TestRequest,
+    ) -> StatusCode {
+        app.call(request.to_request()).await.unwrap().status()
+    }
+
+    #[actix_rt::test]
+    pub async fn categories_with_keys_need_one_of_them() {
+        let category_to_short_names = [
+            (
+                "private".into(),
+                vec![FeedShortName {
+                    value: "invidious".into(),
+                    feed_type: FeedType::Invidious,
+                }],
+            ),
+            (
+                "public".into(),
+                vec![FeedShortName {
+                    value: "HardDriveMag".into(),
+                    feed_type: FeedType::Nitter,
+                }],
+            ),
+        ]
+        .into();
+        let app = start_with(
+            category_to_short_names,
+            None,
+            vec![],
+            vec![("private", "private-key")],
+        )
+        .await;
+        let get = |uri: &str| TestRequest::get().uri(uri);
+
+        assert_eq!(
+            status(&app, get("/feeds/public/atom.xml")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&app, get("/feeds/private/atom.xml")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&app, get("/feeds/private/atom.xml?key=private-key")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(
+                &app,
+                get("/feeds/private/rss.xml")
+                    .insert_header(("Authorization", "Bearer private-key"))
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(
+                &app,
+                get("/feeds/private/rss.xml")
+                    .insert_header(("Authorization", "bearer private-key"))
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&app, get("/feeds/private/atom.xml?key=wrong-key")).await,
+            StatusCode::NOT_FOUND
+        );
+        // Unknown categories can't be told apart from ones with a different key.
+        assert_eq!(
+            status(&app, get("/feeds/unknown/atom.xml")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&app, get("/feeds/unknown/atom.xml?key=private-key")).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_rt::test]
+    pub async fn global_keys_give_access_to_every_category() {
+        let category_to_short_names = [(
+            "comedy".into(),
+            vec![FeedShortName {
+                value: "HardDriveMag".into(),
+                feed_type: FeedType::Nitter,
+            }],
+        )]
+        .into();
+        let app = start_with(
+            category_to_short_names,
+            None,
+            vec!["global-key".into()],
+            vec![("comedy", "comedy-key")],
         )
+        .await;
+        let get = |uri: &str| TestRequest::get().uri(uri);
+
+        assert_eq!(
+            status(&app, get("/feeds/comedy/feed.json?key=global-key")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&app, get("/feeds/comedy/feed.json?key=comedy-key")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&app, get("/feeds/comedy/feed.json")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn every_entry_field_is_part_of_the_etag() {
+        let entry = Entry::for_test("https://example.com/posts/1");
+        let etag = |entry: Entry| {
+            let feed = Feed {
+                title: "Example".into(),
+                link: "https://example.com/feed".try_into().unwrap(),
+                author_name: "Unknown".into(),
+                id: "Example".into(),
+                entries: vec![entry],
+            };
+            FeedVersion::of(&feed, "application/atom+xml").etag
+        };
+
+        assert_eq!(etag(entry.clone()), etag(entry.clone()));
+        assert_ne!(
+            etag(entry.clone()),
+            etag(Entry {
+                author: Some(Author {
+                    name: "James".into(),
+                    uri: None,
+                }),
+                ..entry.clone()
+            })
+        );
+        assert_ne!(
+            etag(entry.clone()),
+            etag(Entry {
+                media: vec![Media {
+                    kind: MediaKind::Enclosure,
+                    url: "https://example.com/episode.mp3".into(),
+                    media_type: Some("audio/mpeg".into()),
+                }],
+                ..entry.clone()
+            })
+        );
+        assert_ne!(
+            etag(entry.clone()),
+            etag(Entry {
+                sources: vec!["https://example.com/feed".into()],
+                ..entry
+            })
+        );
+    }
+
+    #[actix_rt::test]
+    pub async fn unchanged_feeds_are_not_sent_again() {
+        let category_to_short_names = [(
+            "comedy".into(),
+            vec![FeedShortName {
+                value: "HardDriveMag".into(),
+                feed_type: FeedType::Nitter,
+            }],
+        )]
+        .into();
+        let app = start(category_to_short_names).await;
+        let get = || TestRequest::get().uri("/feeds/comedy/atom.xml");
+
+        let response = app.call(get().to_request()).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(
+            headers.get(header::CONTENT_TYPE).unwrap(),
+            "application/atom+xml"
+        );
+        let etag = headers.get(header::ETAG).unwrap().clone();
+        let last_modified = headers.get(header::LAST_MODIFIED).unwrap().clone();
+        assert_eq!(last_modified, "Sat, 16 Apr 2022 22:31:36 GMT");
+
+        assert_eq!(
+            status(&app, get().insert_header((header::IF_NONE_MATCH, etag))).await,
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            status(
+                &app,
+                get().insert_header((header::IF_NONE_MATCH, "\"other\""))
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(
+                &app,
+                get().insert_header((header::IF_MODIFIED_SINCE, last_modified))
+            )
+            .await,
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            status(
+                &app,
+                get().insert_header((header::IF_MODIFIED_SINCE, "Fri, 08 Apr 2022 00:00:00 GMT"))
+            )
+            .await,
+            StatusCode::OK
+        );
+    }
+
+    #[actix_rt::test]
+    pub async fn errors_are_mapped_to_status_codes() {
+        let category_to_short_names = [
+            (
+                "comedy".into(),
+                vec![FeedShortName {
+                    value: "HardDriveMag".into(),
+                    feed_type: FeedType::Nitter,
+                }],
+            ),
+            (
+                "broken".into(),
+                vec![FeedShortName {
+                    value: "ThisFeedDoesNotExist".into(),
+                    feed_type: FeedType::Nitter,
+                }],
+            ),
+        ]
+        .into();
+        let app = start(category_to_short_names).await;
+        let get = |uri: &str| TestRequest::get().uri(uri);
+
+        assert_eq!(
+            status(&app, get("/feeds/unknown/atom.xml")).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&app, get("/feeds/comedy/atom.xml?extract=everything")).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(&app, get("/feeds/broken/atom.xml")).await,
+            StatusCode::BAD_GATEWAY
+        );
+        let response = app
+            .call(get("/feeds/broken/rss.xml").to_request())
+            .await
+            .unwrap();
+        let bytes = body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&bytes[..], b"None of the sources could be fetched");
+    }
+
+    #[actix_rt::test]
+    pub async fn invalid_libreddit_previews_are_bad_requests() {
+        let provider = FeedProvider::from_categories_and_http_client_and_feed_deserializer(
+            HashMap::new(),
+            Rc::new(HashMapHttpClient {
+                hash_map: HashMap::new(),
+            }),
+            Rc::new(default_feed_deserializer()),
+        )
+        .unwrap();
+        let libreddit_url = "https://libreddit.example.com/".try_into().unwrap();
+        let app = init_service(App::new().configure(config_app(
+            Rc::new(move || provider.clone()),
+            None,
+            Some(libreddit_url),
+        )))
+        .await;
+
+        let valid = TestRequest::get().uri(&format!(
+            "/libreddit/ep/{}/preview/external-pre/image.jpg",
+            base64::encode("width=640")
+        ));
+        let response = app.call(valid.to_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "https://libreddit.example.com/preview/external-pre/image.jpg?width=640"
+        );
+        assert_eq!(
+            status(
+                &app,
+                TestRequest::get().uri("/libreddit/ep/not-base64!/preview/image.jpg")
+            )
+            .await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn keys_are_compared_byte_by_byte() {
+        assert!(constant_time_eq("secret", "secret"));
+        assert!(!constant_time_eq("secret", "secreT"));
+        assert!(!constant_time_eq("secret", "secret2"));
     }
 
     #[actix_rt::test]